@@ -0,0 +1,1234 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::Mutex};
+
+use crate::{log_message, LogLevel};
+
+pub const CACHE_DEFAULT_TTL: u64 = 24 * 60 * 60;
+pub const CACHE_DEFAULT_MAX_ENTRIES: usize = 5000;
+pub const CACHE_DEFAULT_DIR: &str = "cache";
+/// Seconds between two removals of the expired feed bodies on disk
+const SWEEP_INTERVAL: u64 = 60 * 60;
+
+/// Suffix of the temporary files, unique to each write
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CachedArticle {
+    pub content: String,
+    pub fetched_at: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl CachedArticle {
+    #[must_use]
+    pub fn new(content: String) -> Self {
+        Self {
+            content,
+            fetched_at: now(),
+            etag: None,
+            last_modified: None,
+        }
+    }
+}
+
+/// Body of an upstream feed, kept to send conditional requests
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CachedFeed {
+    pub body: String,
+    pub fetched_at: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// A cached article, as listed by the admin API
+#[derive(Serialize)]
+pub struct ArticleInfo {
+    pub url: String,
+    /// Seconds since the article was fetched
+    pub age: u64,
+    /// Bytes of the extracted article
+    pub size: usize,
+}
+
+/// A cached feed body, as listed by the admin API
+#[derive(Serialize)]
+pub struct BodyInfo {
+    /// Seconds since the body was fetched
+    pub age: u64,
+    /// Bytes of the body
+    pub size: usize,
+}
+
+/// The cached body and articles of a feed, as listed by the admin API
+#[derive(Serialize)]
+pub struct FeedInfo {
+    pub feed: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<BodyInfo>,
+    pub articles: Vec<ArticleInfo>,
+}
+
+/// An article as it is written on disk, the file name is only a hash of the urls
+#[derive(Serialize, Deserialize)]
+struct DiskEntry {
+    feed: String,
+    url: String,
+    #[serde(flatten)]
+    article: CachedArticle,
+}
+
+/// A feed body as it is written on disk, the url is kept to list it
+#[derive(Serialize, Deserialize)]
+struct DiskFeed {
+    #[serde(default)]
+    url: String,
+    #[serde(flatten)]
+    feed: CachedFeed,
+}
+
+enum Backend {
+    Memory {
+        articles: Mutex<HashMap<String, HashMap<String, CachedArticle>>>,
+        feeds: Mutex<HashMap<String, CachedFeed>>,
+    },
+    /// One directory per feed, one file per article, and one file per feed body
+    Disk(PathBuf),
+}
+
+/// Extracted articles, grouped by the feed they were found in.
+pub struct ArticleCache {
+    backend: Backend,
+    ttl: Duration,
+    max_entries: usize,
+    /// Number of articles on disk, so the directory is only walked once the cap is exceeded
+    disk_entries: AtomicUsize,
+    /// Number of feed bodies on disk, bounded by `max_entries` too
+    disk_feeds: AtomicUsize,
+    /// When the expired feed bodies on disk were last removed
+    swept_at: AtomicU64,
+}
+
+#[must_use]
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// `url` as it's parsed, without its fragment, so the feeds are cached under a single key.
+///
+/// The articles of a feed are cached under this key, followed by `#` and the selectors they
+/// were extracted with if any.
+#[must_use]
+pub fn normalize_url(url: &str) -> String {
+    Url::parse(url).map_or_else(
+        |_| url.to_string(),
+        |mut url| {
+            url.set_fragment(None);
+            url.into()
+        },
+    )
+}
+
+/// Whether the articles cached under `key` are articles of `feed`
+fn is_key_of(key: &str, feed: &str) -> bool {
+    key.strip_prefix(feed)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('#'))
+}
+
+/// FNV-1a, used for file names and `ETag`s since it's stable across builds
+#[must_use]
+pub fn hash(value: &str) -> String {
+    let hash = value.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{hash:016x}")
+}
+
+impl Default for ArticleCache {
+    fn default() -> Self {
+        Self::new(
+            Duration::from_secs(CACHE_DEFAULT_TTL),
+            CACHE_DEFAULT_MAX_ENTRIES,
+        )
+    }
+}
+
+impl ArticleCache {
+    #[must_use]
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            backend: Backend::Memory {
+                articles: Mutex::new(HashMap::new()),
+                feeds: Mutex::new(HashMap::new()),
+            },
+            ttl,
+            max_entries,
+            disk_entries: AtomicUsize::new(0),
+            disk_feeds: AtomicUsize::new(0),
+            swept_at: AtomicU64::new(0),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the cache directory can't be created
+    pub fn on_disk(dir: PathBuf, ttl: Duration, max_entries: usize) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let (disk_entries, disk_feeds) = count_on_disk(&dir)?;
+
+        Ok(Self {
+            backend: Backend::Disk(dir),
+            ttl,
+            max_entries,
+            disk_entries: AtomicUsize::new(disk_entries),
+            disk_feeds: AtomicUsize::new(disk_feeds),
+            swept_at: AtomicU64::new(0),
+        })
+    }
+
+    /// Reads `CACHE_TTL` (in seconds), `CACHE_MAX_ENTRIES`, `CACHE_BACKEND` (`memory` or `disk`)
+    /// and `CACHE_DIR`, falling back to the defaults
+    #[must_use]
+    pub fn from_env() -> Self {
+        let ttl = env::var("CACHE_TTL").map_or(CACHE_DEFAULT_TTL, |val| {
+            val.parse().unwrap_or_else(|_| {
+                log_message!(
+                    LogLevel::Warn,
+                    "CACHE_TTL is not a valid number of seconds, defaulting to {CACHE_DEFAULT_TTL}"
+                );
+                CACHE_DEFAULT_TTL
+            })
+        });
+        let max_entries = env::var("CACHE_MAX_ENTRIES").map_or(CACHE_DEFAULT_MAX_ENTRIES, |val| {
+            val.parse().unwrap_or_else(|_| {
+                log_message!(
+                    LogLevel::Warn,
+                    "CACHE_MAX_ENTRIES is not a valid number, defaulting to {CACHE_DEFAULT_MAX_ENTRIES}"
+                );
+                CACHE_DEFAULT_MAX_ENTRIES
+            })
+        });
+        let ttl = Duration::from_secs(ttl);
+
+        match env::var("CACHE_BACKEND").as_deref() {
+            Ok("disk") => {
+                let dir = env::var("CACHE_DIR").unwrap_or_else(|_| CACHE_DEFAULT_DIR.to_string());
+                Self::on_disk(PathBuf::from(&dir), ttl, max_entries).unwrap_or_else(|e| {
+                    log_message!(
+                        LogLevel::Error,
+                        "Can't use {dir} as cache directory, defaulting to memory: {e}"
+                    );
+                    Self::new(ttl, max_entries)
+                })
+            }
+            Ok("memory") | Err(_) => Self::new(ttl, max_entries),
+            Ok(backend) => {
+                log_message!(
+                    LogLevel::Warn,
+                    "Unknown CACHE_BACKEND {backend}, defaulting to memory"
+                );
+                Self::new(ttl, max_entries)
+            }
+        }
+    }
+
+    /// Whether the backend can be used, a file is written and removed in the directory of the
+    /// disk cache
+    pub async fn is_available(&self) -> bool {
+        match &self.backend {
+            Backend::Memory { .. } => true,
+            Backend::Disk(dir) => {
+                let probe = tmp_path(&dir.join("probe"));
+                fs::write(&probe, b"").await.is_ok() && fs::remove_file(&probe).await.is_ok()
+            }
+        }
+    }
+
+    const fn is_fresh(&self, fetched_at: u64, now: u64) -> bool {
+        now.saturating_sub(fetched_at) < self.ttl.as_secs()
+    }
+
+    /// Returns the articles of `urls` that are cached for `feed` and not expired
+    pub async fn get(&self, feed: &str, urls: &[String]) -> HashMap<String, CachedArticle> {
+        let now = now();
+
+        let mut articles = self.entries(feed, urls).await;
+        articles.retain(|_, article| self.is_fresh(article.fetched_at, now));
+        articles
+    }
+
+    /// Returns the expired articles of `urls` that can be revalidated with a conditional request
+    pub async fn get_stale(&self, feed: &str, urls: &[String]) -> HashMap<String, CachedArticle> {
+        let now = now();
+
+        let mut articles = self.entries(feed, urls).await;
+        articles.retain(|_, article| {
+            !self.is_fresh(article.fetched_at, now)
+                && (article.etag.is_some() || article.last_modified.is_some())
+        });
+        articles
+    }
+
+    async fn entries(&self, feed: &str, urls: &[String]) -> HashMap<String, CachedArticle> {
+        match &self.backend {
+            Backend::Memory { articles, .. } => {
+                let feeds = articles.lock().await;
+
+                feeds.get(feed).map_or_else(HashMap::new, |articles| {
+                    urls.iter()
+                        .filter_map(|url| {
+                            articles
+                                .get(url)
+                                .map(|article| (url.clone(), article.clone()))
+                        })
+                        .collect()
+                })
+            }
+            Backend::Disk(dir) => {
+                let feed_dir = dir.join(hash(feed));
+                let mut articles = HashMap::new();
+                for url in urls {
+                    if let Some(entry) = read_entry(&feed_dir.join(format!("{}.json", hash(url))))
+                        .await
+                        .filter(|entry| entry.url == *url)
+                    {
+                        articles.insert(url.clone(), entry.article);
+                    }
+                }
+                articles
+            }
+        }
+    }
+
+    /// Returns the last body of the feed at `url`, whether it's expired or not
+    pub async fn get_feed(&self, url: &str) -> Option<CachedFeed> {
+        match &self.backend {
+            Backend::Memory { feeds, .. } => feeds.lock().await.get(url).cloned(),
+            Backend::Disk(dir) => {
+                let content = fs::read(dir.join(format!("{}.json", hash(url))))
+                    .await
+                    .ok()?;
+                serde_json::from_slice::<DiskFeed>(&content)
+                    .ok()
+                    .map(|entry| entry.feed)
+            }
+        }
+    }
+
+    /// Caches the body of the feed at `url`.
+    /// Expired feeds are removed, then the oldest ones until there are at most `max_entries`.
+    pub async fn store_feed(&self, url: &str, feed: CachedFeed) {
+        let now = now();
+
+        match &self.backend {
+            Backend::Memory { feeds, .. } => {
+                let mut feeds = feeds.lock().await;
+                feeds.retain(|_, feed| self.is_fresh(feed.fetched_at, now));
+                feeds.insert(url.to_string(), feed);
+                if feeds.len() > self.max_entries {
+                    let mut fetched: Vec<(u64, String)> = feeds
+                        .iter()
+                        .map(|(url, feed)| (feed.fetched_at, url.clone()))
+                        .collect();
+                    fetched.sort_unstable();
+                    let excess = feeds.len() - self.max_entries;
+                    for (_, url) in fetched.into_iter().take(excess) {
+                        feeds.remove(&url);
+                    }
+                }
+                drop(feeds);
+            }
+            Backend::Disk(dir) => {
+                let path = dir.join(format!("{}.json", hash(url)));
+                let entry = DiskFeed {
+                    url: url.to_string(),
+                    feed,
+                };
+                let write = async {
+                    let tmp_path = tmp_path(&path);
+                    fs::write(&tmp_path, serde_json::to_vec(&entry)?).await?;
+                    let existed = fs::try_exists(&path).await.unwrap_or(true);
+                    fs::rename(&tmp_path, &path).await?;
+                    if !existed
+                        && self.disk_feeds.fetch_add(1, Ordering::Relaxed) >= self.max_entries
+                    {
+                        self.sweep_feeds_on_disk(dir).await?;
+                    }
+                    Ok::<_, std::io::Error>(())
+                };
+                if let Err(e) = write.await {
+                    log_message!(LogLevel::Warn, "ERROR writing cache of {url}: {e}");
+                }
+            }
+        }
+    }
+
+    /// Caches the newly extracted `articles` of `feed`.
+    /// Articles that are expired or no longer in `feed_urls` are removed.
+    pub async fn store(
+        &self,
+        feed: &str,
+        feed_urls: &[String],
+        articles: HashMap<String, CachedArticle>,
+    ) {
+        let now = now();
+        let in_feed: HashSet<&String> = feed_urls.iter().collect();
+
+        match &self.backend {
+            Backend::Memory {
+                articles: feeds, ..
+            } => {
+                let mut feeds = feeds.lock().await;
+
+                let cached = feeds.entry(feed.to_string()).or_default();
+                cached.retain(|url, article| {
+                    in_feed.contains(url) && self.is_fresh(article.fetched_at, now)
+                });
+                cached.extend(articles);
+                feeds.retain(|_, articles| !articles.is_empty());
+
+                self.evict(&mut feeds);
+                drop(feeds);
+            }
+            Backend::Disk(dir) => {
+                let feed_dir = dir.join(hash(feed));
+                if let Err(e) = self
+                    .store_on_disk(&feed_dir, feed, &in_feed, articles, now)
+                    .await
+                {
+                    log_message!(LogLevel::Warn, "ERROR writing cache of {feed}: {e}");
+                }
+                if let Err(e) = self.evict_on_disk(dir).await {
+                    log_message!(LogLevel::Warn, "ERROR evicting cache entries: {e}");
+                }
+            }
+        }
+    }
+
+    /// The cached feeds and their articles, sorted by url
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the cache directory can't be read
+    pub async fn list(&self) -> std::io::Result<Vec<FeedInfo>> {
+        let now = now();
+        let mut bodies: BTreeMap<String, BodyInfo> = BTreeMap::new();
+        let mut add_body = |url: &str, body: &CachedFeed| {
+            bodies.insert(
+                url.to_string(),
+                BodyInfo {
+                    age: now.saturating_sub(body.fetched_at),
+                    size: body.body.len(),
+                },
+            );
+        };
+        let mut feeds: BTreeMap<String, Vec<ArticleInfo>> = BTreeMap::new();
+        let mut add = |feed: &str, url: &str, article: &CachedArticle| {
+            feeds
+                .entry(feed.to_string())
+                .or_default()
+                .push(ArticleInfo {
+                    url: url.to_string(),
+                    age: now.saturating_sub(article.fetched_at),
+                    size: article.content.len(),
+                });
+        };
+
+        match &self.backend {
+            Backend::Memory { articles, feeds } => {
+                for (url, body) in feeds.lock().await.iter() {
+                    add_body(url, body);
+                }
+                for (feed, articles) in articles.lock().await.iter() {
+                    for (url, article) in articles {
+                        add(feed, url, article);
+                    }
+                }
+            }
+            Backend::Disk(dir) => {
+                let mut feed_dirs = fs::read_dir(dir).await?;
+                while let Some(feed_dir) = feed_dirs.next_entry().await? {
+                    if !feed_dir.file_type().await?.is_dir() {
+                        if let Some(entry) = read_body(&feed_dir.path()).await {
+                            add_body(&entry.url, &entry.feed);
+                        }
+                        continue;
+                    }
+                    let mut files = fs::read_dir(feed_dir.path()).await?;
+                    while let Some(file) = files.next_entry().await? {
+                        if let Some(entry) = read_entry(&file.path()).await {
+                            add(&entry.feed, &entry.url, &entry.article);
+                        }
+                    }
+                }
+            }
+        }
+
+        for feed in bodies.keys() {
+            feeds.entry(feed.clone()).or_default();
+        }
+
+        Ok(feeds
+            .into_iter()
+            .map(|(feed, mut articles)| {
+                articles.sort_unstable_by(|a, b| a.url.cmp(&b.url));
+                FeedInfo {
+                    body: bodies.remove(&feed),
+                    feed,
+                    articles,
+                }
+            })
+            .collect())
+    }
+
+    /// Removes the article at `url` of `feed`, whatever the selectors it was extracted with,
+    /// returns whether it was cached
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file of the article can't be removed
+    pub async fn purge_article(&self, feed: &str, url: &str) -> std::io::Result<bool> {
+        let feed = normalize_url(feed);
+
+        match &self.backend {
+            Backend::Memory { articles, .. } => {
+                let mut feeds = articles.lock().await;
+                let mut removed = false;
+                for (_, articles) in feeds.iter_mut().filter(|(key, _)| is_key_of(key, &feed)) {
+                    removed |= articles.remove(url).is_some();
+                }
+                feeds.retain(|_, articles| !articles.is_empty());
+                drop(feeds);
+                Ok(removed)
+            }
+            Backend::Disk(dir) => {
+                let mut removed = false;
+                for feed_dir in feed_dirs(dir, &feed).await? {
+                    let path = feed_dir.join(format!("{}.json", hash(url)));
+                    if remove_if_exists(fs::remove_file(path).await)? {
+                        forget(&self.disk_entries, 1);
+                        removed = true;
+                    }
+                }
+                Ok(removed)
+            }
+        }
+    }
+
+    /// Removes the body of `feed` and its articles, whatever the selectors they were extracted
+    /// with, returns whether anything was cached
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the files of the feed can't be removed
+    pub async fn purge_feed(&self, feed: &str) -> std::io::Result<bool> {
+        let feed = normalize_url(feed);
+
+        match &self.backend {
+            Backend::Memory { articles, feeds } => {
+                let mut articles = articles.lock().await;
+                let len = articles.len();
+                articles.retain(|key, _| !is_key_of(key, &feed));
+                let removed_articles = articles.len() < len;
+                drop(articles);
+                let removed_body = feeds.lock().await.remove(&feed).is_some();
+                Ok(removed_articles || removed_body)
+            }
+            Backend::Disk(dir) => {
+                let mut removed_articles = false;
+                for feed_dir in feed_dirs(dir, &feed).await? {
+                    let mut count = 0;
+                    let mut files = fs::read_dir(&feed_dir).await?;
+                    while files.next_entry().await?.is_some() {
+                        count += 1;
+                    }
+                    removed_articles |= remove_if_exists(fs::remove_dir_all(feed_dir).await)?;
+                    forget(&self.disk_entries, count);
+                }
+                let removed_body = remove_if_exists(
+                    fs::remove_file(dir.join(format!("{}.json", hash(&feed)))).await,
+                )?;
+                if removed_body {
+                    forget(&self.disk_feeds, 1);
+                }
+                Ok(removed_articles || removed_body)
+            }
+        }
+    }
+
+    /// Removes every article and feed body
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the content of the cache directory can't be removed
+    pub async fn flush(&self) -> std::io::Result<()> {
+        match &self.backend {
+            Backend::Memory { articles, feeds } => {
+                articles.lock().await.clear();
+                feeds.lock().await.clear();
+            }
+            Backend::Disk(dir) => {
+                let mut entries = fs::read_dir(dir).await?;
+                while let Some(entry) = entries.next_entry().await? {
+                    if entry.file_type().await?.is_dir() {
+                        fs::remove_dir_all(entry.path()).await?;
+                    } else {
+                        fs::remove_file(entry.path()).await?;
+                    }
+                }
+                self.disk_entries.store(0, Ordering::Relaxed);
+                self.disk_feeds.store(0, Ordering::Relaxed);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the oldest articles until there are at most `max_entries` articles
+    fn evict(&self, feeds: &mut HashMap<String, HashMap<String, CachedArticle>>) {
+        let len: usize = feeds.values().map(HashMap::len).sum();
+        if len <= self.max_entries {
+            return;
+        }
+
+        let mut entries: Vec<(u64, String, String)> = feeds
+            .iter()
+            .flat_map(|(feed, articles)| {
+                articles
+                    .iter()
+                    .map(|(url, article)| (article.fetched_at, feed.clone(), url.clone()))
+            })
+            .collect();
+        entries.sort_unstable();
+
+        for (_, feed, url) in entries.into_iter().take(len - self.max_entries) {
+            if let Some(articles) = feeds.get_mut(&feed) {
+                articles.remove(&url);
+            }
+        }
+        feeds.retain(|_, articles| !articles.is_empty());
+    }
+
+    async fn store_on_disk(
+        &self,
+        feed_dir: &Path,
+        feed: &str,
+        in_feed: &HashSet<&String>,
+        articles: HashMap<String, CachedArticle>,
+        now: u64,
+    ) -> std::io::Result<()> {
+        fs::create_dir_all(feed_dir).await?;
+
+        let mut files = fs::read_dir(feed_dir).await?;
+        while let Some(file) = files.next_entry().await? {
+            // Another writer is still writing it
+            if is_tmp(&file.path()) {
+                continue;
+            }
+            let keep = read_entry(&file.path()).await.is_some_and(|entry| {
+                in_feed.contains(&entry.url) && self.is_fresh(entry.article.fetched_at, now)
+            });
+            if !keep && remove_if_exists(fs::remove_file(file.path()).await)? {
+                forget(&self.disk_entries, 1);
+            }
+        }
+
+        for (url, article) in articles {
+            let path = feed_dir.join(format!("{}.json", hash(&url)));
+            let entry = DiskEntry {
+                feed: feed.to_string(),
+                url,
+                article,
+            };
+            // Write then rename so a reader never sees a partial file
+            let tmp_path = tmp_path(&path);
+            fs::write(&tmp_path, serde_json::to_vec(&entry)?).await?;
+            let existed = fs::try_exists(&path).await.unwrap_or(true);
+            fs::rename(&tmp_path, &path).await?;
+            if !existed {
+                self.disk_entries.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        if fs::read_dir(feed_dir).await?.next_entry().await?.is_none() {
+            fs::remove_dir(feed_dir).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the feed bodies that are expired, at most once every `SWEEP_INTERVAL`, and the
+    /// least recently written articles once there are more than `max_entries` of them.
+    /// A tenth of the cap is freed at once, so the directory isn't walked on every store.
+    async fn evict_on_disk(&self, dir: &Path) -> std::io::Result<()> {
+        let now = now();
+        if now.saturating_sub(self.swept_at.load(Ordering::Relaxed)) >= SWEEP_INTERVAL {
+            self.swept_at.store(now, Ordering::Relaxed);
+            self.sweep_feeds_on_disk(dir).await?;
+        }
+        if self.disk_entries.load(Ordering::Relaxed) <= self.max_entries {
+            return Ok(());
+        }
+
+        let mut entries: Vec<(SystemTime, PathBuf)> = Vec::new();
+        let mut feed_dirs = fs::read_dir(dir).await?;
+        while let Some(feed_dir) = feed_dirs.next_entry().await? {
+            if !feed_dir.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut files = fs::read_dir(feed_dir.path()).await?;
+            while let Some(file) = files.next_entry().await? {
+                if !is_tmp(&file.path()) {
+                    let modified = file.metadata().await?.modified()?;
+                    entries.push((modified, file.path()));
+                }
+            }
+        }
+
+        let target = self.max_entries - self.max_entries / 10;
+        let len = entries.len();
+        entries.sort_unstable();
+        for (_, path) in entries.into_iter().take(len.saturating_sub(target)) {
+            remove_if_exists(fs::remove_file(path).await)?;
+        }
+        self.disk_entries.store(len.min(target), Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Removes the feed bodies, the files at the top of `dir`, that are expired, then the least
+    /// recently written ones until there are a tenth less than `max_entries`
+    async fn sweep_feeds_on_disk(&self, dir: &Path) -> std::io::Result<()> {
+        let mut bodies: Vec<(SystemTime, PathBuf)> = Vec::new();
+        let mut files = fs::read_dir(dir).await?;
+        while let Some(file) = files.next_entry().await? {
+            if file.file_type().await?.is_dir() || is_tmp(&file.path()) {
+                continue;
+            }
+            let modified = file.metadata().await?.modified()?;
+            if modified.elapsed().is_ok_and(|elapsed| elapsed >= self.ttl) {
+                remove_if_exists(fs::remove_file(file.path()).await)?;
+            } else {
+                bodies.push((modified, file.path()));
+            }
+        }
+
+        let target = self.max_entries - self.max_entries / 10;
+        let len = bodies.len();
+        bodies.sort_unstable();
+        for (_, path) in bodies.into_iter().take(len.saturating_sub(target)) {
+            remove_if_exists(fs::remove_file(path).await)?;
+        }
+        self.disk_feeds.store(len.min(target), Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+/// Subtracts `count` from `counter`, without going below zero
+fn forget(counter: &AtomicUsize, count: usize) {
+    let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+        Some(value.saturating_sub(count))
+    });
+}
+
+/// A temporary file next to `path`, unique to this process and this write
+fn tmp_path(path: &Path) -> PathBuf {
+    let counter = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_extension(format!("{}.{counter}.tmp", process::id()))
+}
+
+fn is_tmp(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "tmp")
+}
+
+/// Number of articles in the feed directories of `dir`, and of feed bodies at its top
+fn count_on_disk(dir: &Path) -> std::io::Result<(usize, usize)> {
+    let mut articles = 0;
+    let mut feeds = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            articles += std::fs::read_dir(entry.path())?
+                .filter_map(Result::ok)
+                .filter(|file| !is_tmp(&file.path()))
+                .count();
+        } else if !is_tmp(&entry.path()) {
+            feeds += 1;
+        }
+    }
+
+    Ok((articles, feeds))
+}
+
+/// Directories of the articles cached under a key of `feed`, known from their first article
+async fn feed_dirs(dir: &Path, feed: &str) -> std::io::Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    let mut feed_dirs = fs::read_dir(dir).await?;
+    while let Some(feed_dir) = feed_dirs.next_entry().await? {
+        if !feed_dir.file_type().await?.is_dir() {
+            continue;
+        }
+        let mut files = fs::read_dir(feed_dir.path()).await?;
+        while let Some(file) = files.next_entry().await? {
+            if let Some(entry) = read_entry(&file.path()).await {
+                if is_key_of(&entry.feed, feed) {
+                    dirs.push(feed_dir.path());
+                }
+                break;
+            }
+        }
+    }
+
+    Ok(dirs)
+}
+
+/// Whether a file was removed, a missing file isn't an error
+fn remove_if_exists(result: std::io::Result<()>) -> std::io::Result<bool> {
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+async fn read_entry(path: &Path) -> Option<DiskEntry> {
+    let content = fs::read(path).await.ok()?;
+
+    serde_json::from_slice(&content)
+        .map_err(|e| {
+            log_message!(
+                LogLevel::Warn,
+                "ERROR reading cache entry {}: {e}",
+                path.display()
+            );
+        })
+        .ok()
+}
+
+/// The body of a feed on disk, `None` for other files and bodies written without their url
+async fn read_body(path: &Path) -> Option<DiskFeed> {
+    if is_tmp(path) {
+        return None;
+    }
+    let content = fs::read(path).await.ok()?;
+
+    serde_json::from_slice::<DiskFeed>(&content)
+        .ok()
+        .filter(|entry| !entry.url.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(urls: &[&str]) -> Vec<String> {
+        urls.iter().map(ToString::to_string).collect()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("furss-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_is_available() {
+        assert!(ArticleCache::default().is_available().await);
+
+        let dir = temp_dir("available");
+        let cache =
+            ArticleCache::on_disk(dir.clone(), Duration::from_secs(CACHE_DEFAULT_TTL), 10).unwrap();
+        assert!(cache.is_available().await);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!cache.is_available().await);
+    }
+
+    async fn test_purge(cache: ArticleCache) {
+        let feed = "https://example.org/feed";
+        let selected = "https://example.org/feed#selector=article";
+        let other = "https://example.org/feed2";
+        let feed_urls = urls(&["https://example.org/1", "https://example.org/2"]);
+        let articles: HashMap<_, _> = feed_urls
+            .iter()
+            .map(|url| (url.clone(), CachedArticle::new(format!("Article {url}"))))
+            .collect();
+        for key in [feed, selected, other] {
+            cache.store(key, &feed_urls, articles.clone()).await;
+        }
+        let body = CachedFeed {
+            body: "<rss></rss>".to_string(),
+            fetched_at: now(),
+            etag: None,
+            last_modified: None,
+        };
+        cache.store_feed(feed, body).await;
+
+        let list = cache.list().await.unwrap();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[0].feed, feed);
+        assert_eq!(list[0].body.as_ref().map(|body| body.size), Some(11));
+        assert_eq!(list[0].articles[0].url, feed_urls[0]);
+        assert_eq!(list[0].articles[0].size, 29);
+        assert_eq!(list[1].feed, selected);
+        assert!(list[1].body.is_none());
+
+        assert!(cache.purge_article(feed, &feed_urls[0]).await.unwrap());
+        assert!(!cache.purge_article(feed, &feed_urls[0]).await.unwrap());
+        assert_eq!(cache.get(feed, &feed_urls).await.len(), 1);
+        assert_eq!(cache.get(selected, &feed_urls).await.len(), 1);
+        assert_eq!(cache.get(other, &feed_urls).await.len(), 2);
+
+        assert!(cache
+            .purge_feed("HTTPS://Example.org/feed#top")
+            .await
+            .unwrap());
+        assert!(!cache.purge_feed(feed).await.unwrap());
+        assert!(cache.get_feed(feed).await.is_none());
+        assert!(cache.get(selected, &feed_urls).await.is_empty());
+        let list = cache.list().await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].feed, other);
+
+        cache.flush().await.unwrap();
+        assert!(cache.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_purge_memory() {
+        test_purge(ArticleCache::default()).await;
+    }
+
+    #[tokio::test]
+    async fn test_purge_disk() {
+        let dir = temp_dir("purge");
+        test_purge(
+            ArticleCache::on_disk(dir.clone(), Duration::from_secs(CACHE_DEFAULT_TTL), 10).unwrap(),
+        )
+        .await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_store_and_get() {
+        let cache = ArticleCache::default();
+        let feed_urls = urls(&["https://example.org/1", "https://example.org/2"]);
+
+        cache
+            .store(
+                "https://example.org/feed",
+                &feed_urls,
+                HashMap::from([(
+                    feed_urls[0].clone(),
+                    CachedArticle::new("First".to_string()),
+                )]),
+            )
+            .await;
+
+        let articles = cache.get("https://example.org/feed", &feed_urls).await;
+        assert_eq!(articles.len(), 1);
+        assert_eq!(articles[&feed_urls[0]].content, "First");
+        assert!(cache
+            .get("https://other.org/feed", &feed_urls)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_store_removes_articles_not_in_feed() {
+        let cache = ArticleCache::default();
+        let feed = "https://example.org/feed";
+
+        cache
+            .store(
+                feed,
+                &urls(&["https://example.org/1"]),
+                HashMap::from([(
+                    "https://example.org/1".to_string(),
+                    CachedArticle::new("First".to_string()),
+                )]),
+            )
+            .await;
+        cache
+            .store(
+                feed,
+                &urls(&["https://example.org/2"]),
+                HashMap::from([(
+                    "https://example.org/2".to_string(),
+                    CachedArticle::new("Second".to_string()),
+                )]),
+            )
+            .await;
+
+        let articles = cache
+            .get(
+                feed,
+                &urls(&["https://example.org/1", "https://example.org/2"]),
+            )
+            .await;
+        assert_eq!(articles.len(), 1);
+        assert!(articles.contains_key("https://example.org/2"));
+    }
+
+    #[tokio::test]
+    async fn test_expired_articles_are_not_returned() {
+        let cache = ArticleCache::new(Duration::ZERO, CACHE_DEFAULT_MAX_ENTRIES);
+        let feed_urls = urls(&["https://example.org/1"]);
+
+        cache
+            .store(
+                "https://example.org/feed",
+                &feed_urls,
+                HashMap::from([(
+                    feed_urls[0].clone(),
+                    CachedArticle::new("First".to_string()),
+                )]),
+            )
+            .await;
+
+        assert!(cache
+            .get("https://example.org/feed", &feed_urls)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_max_entries() {
+        let cache = ArticleCache::new(Duration::from_secs(CACHE_DEFAULT_TTL), 2);
+        let feed_urls = urls(&[
+            "https://example.org/1",
+            "https://example.org/2",
+            "https://example.org/3",
+        ]);
+
+        cache
+            .store(
+                "https://example.org/feed",
+                &feed_urls,
+                feed_urls
+                    .iter()
+                    .map(|url| (url.clone(), CachedArticle::new(url.clone())))
+                    .collect(),
+            )
+            .await;
+
+        assert_eq!(
+            cache
+                .get("https://example.org/feed", &feed_urls)
+                .await
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disk_max_entries() {
+        let dir = temp_dir("max-entries");
+        let cache =
+            ArticleCache::on_disk(dir.clone(), Duration::from_secs(CACHE_DEFAULT_TTL), 2).unwrap();
+        let feed_urls = urls(&[
+            "https://example.org/1",
+            "https://example.org/2",
+            "https://example.org/3",
+        ]);
+
+        cache
+            .store(
+                "https://example.org/feed",
+                &feed_urls,
+                feed_urls
+                    .iter()
+                    .map(|url| (url.clone(), CachedArticle::new(url.clone())))
+                    .collect(),
+            )
+            .await;
+
+        assert_eq!(
+            cache
+                .get("https://example.org/feed", &feed_urls)
+                .await
+                .len(),
+            2
+        );
+        // The count is restored from the directory
+        let cache =
+            ArticleCache::on_disk(dir.clone(), Duration::from_secs(CACHE_DEFAULT_TTL), 2).unwrap();
+        assert_eq!(cache.disk_entries.load(Ordering::Relaxed), 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_survives_restart() {
+        let dir = temp_dir("restart");
+        let feed_urls = urls(&["https://example.org/1", "https://example.org/2"]);
+        let mut article = CachedArticle::new("First".to_string());
+        article.etag = Some("\"abc\"".to_string());
+
+        ArticleCache::on_disk(dir.clone(), Duration::from_secs(CACHE_DEFAULT_TTL), 10)
+            .unwrap()
+            .store(
+                "https://example.org/feed",
+                &feed_urls,
+                HashMap::from([(feed_urls[0].clone(), article)]),
+            )
+            .await;
+
+        let cache =
+            ArticleCache::on_disk(dir.clone(), Duration::from_secs(CACHE_DEFAULT_TTL), 10).unwrap();
+        let articles = cache.get("https://example.org/feed", &feed_urls).await;
+        assert_eq!(articles.len(), 1);
+        assert_eq!(articles[&feed_urls[0]].content, "First");
+        assert_eq!(articles[&feed_urls[0]].etag.as_deref(), Some("\"abc\""));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_removes_articles_not_in_feed() {
+        let dir = temp_dir("retain");
+        let cache =
+            ArticleCache::on_disk(dir.clone(), Duration::from_secs(CACHE_DEFAULT_TTL), 10).unwrap();
+        let feed = "https://example.org/feed";
+
+        cache
+            .store(
+                feed,
+                &urls(&["https://example.org/1"]),
+                HashMap::from([(
+                    "https://example.org/1".to_string(),
+                    CachedArticle::new("First".to_string()),
+                )]),
+            )
+            .await;
+        cache
+            .store(
+                feed,
+                &urls(&["https://example.org/2"]),
+                HashMap::from([(
+                    "https://example.org/2".to_string(),
+                    CachedArticle::new("Second".to_string()),
+                )]),
+            )
+            .await;
+
+        let articles = cache
+            .get(
+                feed,
+                &urls(&["https://example.org/1", "https://example.org/2"]),
+            )
+            .await;
+        assert_eq!(articles.len(), 1);
+        assert!(articles.contains_key("https://example.org/2"));
+        assert_eq!(std::fs::read_dir(dir.join(hash(feed))).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stale_articles_can_be_revalidated() {
+        let cache = ArticleCache::new(Duration::ZERO, CACHE_DEFAULT_MAX_ENTRIES);
+        let feed_urls = urls(&["https://example.org/1", "https://example.org/2"]);
+        let mut article = CachedArticle::new("First".to_string());
+        article.etag = Some("\"abc\"".to_string());
+
+        // Store doesn't remove the articles it's given, even expired
+        cache
+            .store(
+                "https://example.org/feed",
+                &feed_urls,
+                HashMap::from([
+                    (feed_urls[0].clone(), article),
+                    (
+                        feed_urls[1].clone(),
+                        CachedArticle::new("Second".to_string()),
+                    ),
+                ]),
+            )
+            .await;
+
+        let stale = cache
+            .get_stale("https://example.org/feed", &feed_urls)
+            .await;
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[&feed_urls[0]].etag.as_deref(), Some("\"abc\""));
+    }
+
+    #[tokio::test]
+    async fn test_store_and_get_feed() {
+        let dir = temp_dir("feed");
+        let cache =
+            ArticleCache::on_disk(dir.clone(), Duration::from_secs(CACHE_DEFAULT_TTL), 10).unwrap();
+        let feed = CachedFeed {
+            body: "<rss></rss>".to_string(),
+            fetched_at: now(),
+            etag: Some("\"abc\"".to_string()),
+            last_modified: None,
+        };
+
+        cache.store_feed("https://example.org/feed", feed).await;
+        assert_eq!(
+            cache
+                .get_feed("https://example.org/feed")
+                .await
+                .map(|feed| feed.body),
+            Some("<rss></rss>".to_string())
+        );
+        assert!(cache.get_feed("https://other.org/feed").await.is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_feed_max_entries() {
+        let dir = temp_dir("feed-max-entries");
+        let ttl = Duration::from_secs(CACHE_DEFAULT_TTL);
+        let feeds = [
+            "https://example.org/1",
+            "https://example.org/2",
+            "https://example.org/3",
+        ];
+
+        for cache in [
+            ArticleCache::new(ttl, 2),
+            ArticleCache::on_disk(dir.clone(), ttl, 2).unwrap(),
+        ] {
+            for (age, url) in (0..3).rev().zip(feeds) {
+                let feed = CachedFeed {
+                    body: "<rss></rss>".to_string(),
+                    fetched_at: now() - age,
+                    etag: None,
+                    last_modified: None,
+                };
+                cache.store_feed(url, feed).await;
+            }
+
+            let mut cached = Vec::new();
+            for url in feeds {
+                if cache.get_feed(url).await.is_some() {
+                    cached.push(url);
+                }
+            }
+            // Files written in the same instant on disk could be evicted in any order
+            assert_eq!(cached.len(), 2);
+            assert!(
+                matches!(cache.backend, Backend::Disk(_))
+                    || cached == ["https://example.org/2", "https://example.org/3"]
+            );
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
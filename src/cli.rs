@@ -0,0 +1,127 @@
+use std::{error::Error, path::PathBuf};
+
+use argh::FromArgs;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    cache::ArticleCache,
+    config::WatchedFile,
+    feed::FeedFormat,
+    parse::{add_http_prefix, get_rss_feed},
+    rules::SiteRules,
+    FurssOptions,
+};
+
+#[derive(FromArgs)]
+/// Get full articles from RSS feeds.
+pub struct Args {
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum Command {
+    Fetch(FetchArgs),
+}
+
+#[derive(FromArgs)]
+/// Fetch a feed and write it back with the full articles.
+#[argh(subcommand, name = "fetch")]
+pub struct FetchArgs {
+    /// url of the feed
+    #[argh(positional)]
+    pub url: String,
+
+    /// write the feed to this file instead of stdout
+    #[argh(option, short = 'o')]
+    pub output: Option<PathBuf>,
+
+    /// fetch the articles of every item of the feed
+    #[argh(switch)]
+    pub full: bool,
+
+    /// number of items to fetch when not in full mode (default: 10)
+    #[argh(option)]
+    pub number_items: Option<u16>,
+
+    /// url of a flaresolverr instance used to get the feed
+    #[argh(option)]
+    pub flaresolverr: Option<String>,
+
+    /// http, https or socks5 proxy used for every request
+    #[argh(option)]
+    pub proxy: Option<String>,
+
+    /// username of the proxy
+    #[argh(option)]
+    pub proxy_username: Option<String>,
+
+    /// password of the proxy
+    #[argh(option)]
+    pub proxy_password: Option<String>,
+
+    /// ignore cached articles and fetch them again
+    #[argh(switch)]
+    pub disable_cache: bool,
+
+    /// remove the items whose article can't be fetched
+    #[argh(switch)]
+    pub drop_failed: bool,
+
+    /// convert the feed to this format: rss, atom or json
+    #[argh(option)]
+    pub format: Option<FeedFormat>,
+
+    /// selector of the element holding the articles, overrides the site rules
+    #[argh(option)]
+    pub selector: Option<String>,
+
+    /// comma separated selectors of the elements removed from the articles
+    #[argh(option)]
+    pub remove: Option<String>,
+}
+
+impl From<&FetchArgs> for FurssOptions {
+    fn from(args: &FetchArgs) -> Self {
+        Self {
+            flaresolverr: args.flaresolverr.clone(),
+            proxy: args.proxy.clone(),
+            proxy_username: args.proxy_username.clone(),
+            proxy_password: args.proxy_password.clone(),
+            full: Some(args.full),
+            number_items: args.number_items,
+            disable_cache: Some(args.disable_cache),
+            drop_failed: Some(args.drop_failed),
+            format: args.format,
+            selector: args.selector.clone(),
+            remove: args.remove.clone(),
+            preview: None,
+        }
+    }
+}
+
+/// # Errors
+///
+/// Will return `Err` if the feed can't be fetched or the output can't be written
+pub async fn fetch(args: &FetchArgs) -> Result<(), Box<dyn Error>> {
+    let options = FurssOptions::from(args);
+    let rules = WatchedFile::<SiteRules>::from_env("RULES_FILE").get().await;
+    let feed = get_rss_feed(
+        &add_http_prefix(&args.url),
+        &options,
+        &ArticleCache::from_env(),
+        &rules,
+    )
+    .await?;
+
+    if let Some(path) = &args.output {
+        tokio::fs::write(path, feed.body).await?;
+    } else {
+        let mut stdout = tokio::io::stdout();
+        stdout.write_all(feed.body.as_bytes()).await?;
+        stdout.flush().await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use std::{env, path::PathBuf, sync::Arc, time::SystemTime};
+
+use serde::de::DeserializeOwned;
+use tokio::{fs, sync::Mutex};
+
+use crate::{log_message, LogLevel};
+
+struct Loaded<T> {
+    modified: Option<SystemTime>,
+    value: Arc<T>,
+    /// The last read or parse of the file failed
+    failed: bool,
+}
+
+/// A TOML file that is read again when it changes on disk
+pub struct WatchedFile<T> {
+    path: Option<PathBuf>,
+    loaded: Mutex<Loaded<T>>,
+}
+
+impl<T: DeserializeOwned + Default + Send + Sync> WatchedFile<T> {
+    #[must_use]
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            loaded: Mutex::new(Loaded {
+                modified: None,
+                value: Arc::new(T::default()),
+                failed: false,
+            }),
+        }
+    }
+
+    /// Uses the path in the environment variable `name`, if any
+    #[must_use]
+    pub fn from_env(name: &str) -> Self {
+        Self::new(env::var(name).ok().map(PathBuf::from))
+    }
+
+    /// Returns the content of the file, reading it again if it was modified.
+    /// If the file can't be read or parsed, the previous content is kept.
+    pub async fn get(&self) -> Arc<T> {
+        let mut loaded = self.loaded.lock().await;
+        let Some(path) = &self.path else {
+            return loaded.value.clone();
+        };
+
+        let modified = match fs::metadata(path)
+            .await
+            .and_then(|metadata| metadata.modified())
+        {
+            Ok(modified) => modified,
+            Err(e) => {
+                log_message!(LogLevel::Warn, "ERROR reading {}: {e}", path.display());
+                loaded.failed = true;
+                return loaded.value.clone();
+            }
+        };
+        if loaded.modified == Some(modified) {
+            return loaded.value.clone();
+        }
+
+        // Don't retry a broken file until it's modified again
+        loaded.modified = Some(modified);
+        match fs::read_to_string(path)
+            .await
+            .map(|content| toml::from_str::<T>(&content))
+        {
+            Ok(Ok(value)) => {
+                log_message!(LogLevel::Info, "Loaded {}", path.display());
+                loaded.value = Arc::new(value);
+                loaded.failed = false;
+            }
+            Ok(Err(e)) => {
+                log_message!(LogLevel::Error, "ERROR parsing {}: {e}", path.display());
+                loaded.failed = true;
+            }
+            Err(e) => {
+                log_message!(LogLevel::Warn, "ERROR reading {}: {e}", path.display());
+                loaded.failed = true;
+            }
+        }
+
+        loaded.value.clone()
+    }
+
+    /// Whether the current content of the file is loaded, always true without a file
+    pub async fn is_loaded(&self) -> bool {
+        self.get().await;
+        !self.loaded.lock().await.failed
+    }
+}
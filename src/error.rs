@@ -0,0 +1,164 @@
+use std::{error::Error, fmt};
+
+use quick_xml::Reader;
+use reqwest::StatusCode;
+
+#[derive(Debug)]
+pub enum FurssError {
+    /// The url of the feed isn't valid
+    InvalidUrl(String),
+    /// An option of the request, like the proxy, isn't valid
+    InvalidOption(String),
+    /// The upstream answered with an error status
+    Upstream(StatusCode),
+    /// The upstream can't be reached
+    Request(reqwest::Error),
+    /// The upstream took too long to answer
+    Timeout,
+    /// `FlareSolverr` couldn't download the feed
+    Flaresolverr(String),
+    /// The feed isn't valid xml
+    Xml {
+        position: u64,
+        error: quick_xml::Error,
+    },
+    /// The feed isn't a valid JSON Feed
+    Json(serde_json::Error),
+    /// The upstream answered with something else than an RSS, Atom or JSON feed
+    NotAFeed,
+    /// The article of an item can't be extracted
+    Extraction {
+        url: String,
+        message: String,
+    },
+    Io(std::io::Error),
+}
+
+impl FurssError {
+    /// An xml error, at the position `reader` is
+    #[must_use]
+    pub const fn xml<R>(reader: &Reader<R>, error: quick_xml::Error) -> Self {
+        Self::Xml {
+            position: reader.buffer_position(),
+            error,
+        }
+    }
+
+    /// Name of the variant, used as a label of the metrics
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidUrl(_) => "invalid_url",
+            Self::InvalidOption(_) => "invalid_option",
+            Self::Upstream(_) => "upstream",
+            Self::Request(_) => "request",
+            Self::Timeout => "timeout",
+            Self::Flaresolverr(_) => "flaresolverr",
+            Self::Xml { .. } => "xml",
+            Self::Json(_) => "json",
+            Self::NotAFeed => "not_a_feed",
+            Self::Extraction { .. } => "extraction",
+            Self::Io(_) => "io",
+        }
+    }
+
+    /// Status of the response when the feed can't be served
+    #[must_use]
+    pub const fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidUrl(_) | Self::InvalidOption(_) => StatusCode::BAD_REQUEST,
+            Self::Upstream(_)
+            | Self::Request(_)
+            | Self::Flaresolverr(_)
+            | Self::Xml { .. }
+            | Self::Json(_) => StatusCode::BAD_GATEWAY,
+            Self::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            Self::NotAFeed => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Extraction { .. } | Self::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for FurssError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl(url) => write!(f, "Invalid url {url}"),
+            Self::InvalidOption(message) => write!(f, "Invalid option: {message}"),
+            Self::Upstream(status) => write!(f, "Upstream answered with {status}"),
+            Self::Request(error) => write!(f, "Error reaching the upstream: {error}"),
+            Self::Timeout => write!(f, "Upstream timed out"),
+            Self::Flaresolverr(message) => write!(f, "FlareSolverr failed: {message}"),
+            Self::Xml { position, error } => {
+                write!(f, "Invalid feed at position {position}: {error}")
+            }
+            Self::Json(error) => write!(f, "Invalid JSON Feed: {error}"),
+            Self::NotAFeed => write!(f, "Upstream isn't an RSS, Atom or JSON feed"),
+            Self::Extraction { url, message } => {
+                write!(f, "Error extracting the article of {url}: {message}")
+            }
+            Self::Io(error) => write!(f, "Error writing the feed: {error}"),
+        }
+    }
+}
+
+impl Error for FurssError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Request(error) => Some(error),
+            Self::Xml { error, .. } => Some(error),
+            Self::Json(error) => Some(error),
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for FurssError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            Self::Timeout
+        } else if let Some(status) = error.status() {
+            Self::Upstream(status)
+        } else {
+            Self::Request(error)
+        }
+    }
+}
+
+impl From<serde_json::Error> for FurssError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Json(error)
+    }
+}
+
+impl From<std::io::Error> for FurssError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_code() {
+        assert_eq!(
+            FurssError::Upstream(StatusCode::NOT_FOUND).status_code(),
+            StatusCode::BAD_GATEWAY
+        );
+        assert_eq!(
+            FurssError::Timeout.status_code(),
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        assert_eq!(
+            FurssError::NotAFeed.status_code(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(FurssError::NotAFeed.kind(), "not_a_feed");
+        assert_eq!(
+            FurssError::InvalidUrl("http://".to_string()).to_string(),
+            "Invalid url http://"
+        );
+    }
+}
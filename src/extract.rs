@@ -0,0 +1,305 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+};
+
+use tl::{HTMLTag, Node, Parser, VDom};
+
+use crate::{
+    html::{is_void, push_end_tag, push_start_tag},
+    rules::SiteRule,
+};
+
+/// Elements that usually hold the article, tried in order before scoring the page
+const CONTENT_SELECTORS: &[&str] = &[
+    "article",
+    "[itemprop=articleBody]",
+    ".entry-content",
+    ".post-content",
+    ".article-content",
+    ".article-body",
+    ".post-body",
+    ".story-body",
+    "main",
+    "[role=main]",
+];
+/// Elements that can hold paragraphs when scoring the page
+const CANDIDATES: &[&str] = &["div", "section", "td", "main", "article", "body"];
+const PARAGRAPHS: &[&str] = &["p", "pre", "blockquote"];
+const POSITIVE_NAMES: &[&str] = &[
+    "article", "body", "content", "entry", "main", "page", "post", "text", "blog", "story",
+];
+const NEGATIVE_NAMES: &[&str] = &[
+    "comment", "footer", "sidebar", "nav", "menu", "share", "social", "related", "promo", "widget",
+    "header", "banner", "sponsor",
+];
+/// Paragraphs shorter than this are ignored when scoring
+const MIN_PARAGRAPH_LENGTH: usize = 25;
+const MIN_SCORE: f64 = 5.0;
+
+/// Returns the html of the main content of a page.
+///
+/// The selector of the site `rule` is used first, then well known content elements,
+/// then every element holding paragraphs is scored on its text, commas and link density.
+///
+/// # Errors
+///
+/// Will return `Err` if the page can't be parsed or if no content is found
+pub fn extract_content(
+    content: &str,
+    rule: Option<&SiteRule>,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let dom = tl::parse(content, tl::ParserOptions::default())?;
+
+    let tag = rule
+        .and_then(|rule| rule.selector.as_deref())
+        .and_then(|selector| select(&dom, selector))
+        .or_else(|| {
+            CONTENT_SELECTORS
+                .iter()
+                .find_map(|selector| select(&dom, selector))
+        })
+        .or_else(|| best_candidate(&dom))
+        .ok_or("No content found")?;
+
+    let removed: HashSet<u32> = rule
+        .map(|rule| {
+            rule.remove
+                .iter()
+                .filter_map(|selector| dom.query_selector(selector))
+                .flatten()
+                .map(|handle| handle.get_inner())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if removed.is_empty() {
+        Ok(std::str::from_utf8(tag.raw().as_bytes())?.to_owned())
+    } else {
+        let mut html = String::new();
+        render(tag, dom.parser(), &removed, &mut html);
+        Ok(html)
+    }
+}
+
+/// Writes the html of `tag` without the `removed` nodes
+fn render(tag: &HTMLTag, parser: &Parser, removed: &HashSet<u32>, html: &mut String) {
+    let name = tag.name().as_utf8_str();
+    push_start_tag(html, &name, tag.attributes().iter());
+    if is_void(&name) {
+        return;
+    }
+
+    for handle in tag.children().top().iter() {
+        if removed.contains(&handle.get_inner()) {
+            continue;
+        }
+        match handle.get(parser) {
+            Some(Node::Tag(child)) => render(child, parser, removed, html),
+            Some(Node::Raw(text)) => html.push_str(&text.as_utf8_str()),
+            Some(Node::Comment(_)) | None => (),
+        }
+    }
+
+    push_end_tag(html, &name);
+}
+
+/// First element matching `selector` that has some text
+fn select<'a>(dom: &'a VDom<'a>, selector: &str) -> Option<&'a HTMLTag<'a>> {
+    let parser = dom.parser();
+
+    dom.query_selector(selector)?
+        .filter_map(|handle| handle.get(parser).and_then(Node::as_tag))
+        .find(|tag| !tag.inner_text(parser).trim().is_empty())
+}
+
+fn best_candidate<'a>(dom: &'a VDom<'a>) -> Option<&'a HTMLTag<'a>> {
+    let parser = dom.parser();
+    let nodes = dom.nodes();
+
+    // Score of the paragraphs that are direct children of each candidate
+    let paragraph_scores: HashMap<usize, f64> = nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(index, node)| {
+            let tag = node.as_tag().filter(|tag| is_one_of(tag, CANDIDATES))?;
+            let score: f64 = tag
+                .children()
+                .top()
+                .iter()
+                .filter_map(|handle| handle.get(parser).and_then(Node::as_tag))
+                .filter(|child| is_one_of(child, PARAGRAPHS))
+                .map(|paragraph| paragraph_score(&paragraph.inner_text(parser)))
+                .sum();
+            (score > 0.0).then_some((index, score))
+        })
+        .collect();
+
+    paragraph_scores
+        .keys()
+        .filter_map(|&index| {
+            let tag = nodes[index].as_tag()?;
+            // Paragraphs of the children count for half, like in readability
+            let children_score: f64 = tag
+                .children()
+                .top()
+                .iter()
+                .filter_map(|handle| paragraph_scores.get(&(handle.get_inner() as usize)))
+                .sum();
+            let score = (paragraph_scores[&index] + children_score / 2.0 + name_score(tag))
+                * (1.0 - link_density(tag, parser));
+            Some((score, tag))
+        })
+        .filter(|(score, _)| *score >= MIN_SCORE)
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, tag)| tag)
+}
+
+fn is_one_of(tag: &HTMLTag, names: &[&str]) -> bool {
+    let name = tag.name().as_utf8_str();
+    names.iter().any(|n| name.eq_ignore_ascii_case(n))
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn paragraph_score(text: &str) -> f64 {
+    let text = text.trim();
+    if text.len() < MIN_PARAGRAPH_LENGTH {
+        return 0.0;
+    }
+
+    let commas = text.matches(',').count() as f64;
+    1.0 + commas + (text.len() as f64 / 100.0).min(3.0)
+}
+
+/// Bonus or malus from the class and id of the element
+fn name_score(tag: &HTMLTag) -> f64 {
+    let attributes = tag.attributes();
+    let names = [attributes.class(), attributes.id()]
+        .into_iter()
+        .flatten()
+        .map(|name| name.as_utf8_str().to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut score = 0.0;
+    if POSITIVE_NAMES.iter().any(|name| names.contains(name)) {
+        score += 25.0;
+    }
+    if NEGATIVE_NAMES.iter().any(|name| names.contains(name)) {
+        score -= 25.0;
+    }
+    score
+}
+
+/// Share of the text of `tag` that is inside links
+#[allow(clippy::cast_precision_loss)]
+fn link_density(tag: &HTMLTag, parser: &Parser) -> f64 {
+    let text_length = tag.inner_text(parser).trim().len();
+    if text_length == 0 {
+        return 1.0;
+    }
+
+    let link_length: usize = tag
+        .children()
+        .all(parser)
+        .iter()
+        .filter_map(Node::as_tag)
+        .filter(|child| is_one_of(child, &["a"]))
+        .map(|link| link.inner_text(parser).trim().len())
+        .sum();
+
+    (link_length as f64 / text_length as f64).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_content_without_script_tags() {
+        let content = r"<html><head><title>Test</title></head><body><article><h1>Article Title</h1><p>Article content goes here.</p></article></body></html>";
+
+        assert_eq!(
+            extract_content(content, None).unwrap(),
+            "<article><h1>Article Title</h1><p>Article content goes here.</p></article>"
+        );
+    }
+
+    #[test]
+    fn test_extract_content_with_script_tags() {
+        let content = r#"<html><head><title>Test</title></head><body><script>console.log("This is a script")<script><article><h1>Article Title</h1><p>Article content goes here.</p></article></body></html>"#;
+
+        assert_eq!(
+            extract_content(content, None).unwrap(),
+            "<article><h1>Article Title</h1><p>Article content goes here.</p></article>"
+        );
+    }
+
+    #[test]
+    fn test_extract_content_no_content() {
+        let content = r"<html><head><title>Test</title></head><body><div><h1>Another Title</h1><p>Some content</p></div></body><html>";
+
+        let result = extract_content(content, None);
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err().to_string(), "No content found");
+    }
+
+    #[test]
+    fn test_extract_content_main_tag() {
+        let content =
+            r"<html><body><nav>Menu</nav><main><h1>Title</h1><p>Text</p></main></body></html>";
+
+        assert_eq!(
+            extract_content(content, None).unwrap(),
+            "<main><h1>Title</h1><p>Text</p></main>"
+        );
+    }
+
+    #[test]
+    fn test_extract_content_content_class() {
+        let content = r#"<html><body><div class="sidebar">Links</div><div class="post-content"><p>Text</p></div></body></html>"#;
+
+        assert_eq!(
+            extract_content(content, None).unwrap(),
+            r#"<div class="post-content"><p>Text</p></div>"#
+        );
+    }
+
+    #[test]
+    fn test_extract_content_scoring() {
+        let content = r#"<html><body><div id="menu"><p><a href="/">A link to the home page, in the menu</a></p></div><div id="a"><p>This is the first paragraph of the article, with some commas, in it.</p><p>This is the second paragraph of the article, it is long enough.</p></div><div id="comments"><p>This is a comment about the article, with a comma.</p></div></body></html>"#;
+
+        let result = extract_content(content, None).unwrap();
+        assert!(result.starts_with(r#"<div id="a">"#));
+    }
+
+    #[test]
+    fn test_extract_content_site_rule() {
+        let content = r#"<html><body><article><p>Teaser</p></article><div class="story"><p>Text</p><div class="share"><a href="/share">Share</a></div><img src="/a.png"><p>End</p></div></body></html>"#;
+        let rule = SiteRule {
+            domain: "example.com".to_string(),
+            selector: Some("div.story".to_string()),
+            remove: vec![".share".to_string()],
+        };
+
+        assert_eq!(
+            extract_content(content, Some(&rule)).unwrap(),
+            r#"<div class="story"><p>Text</p><img src="/a.png"><p>End</p></div>"#
+        );
+    }
+
+    #[test]
+    fn test_extract_content_site_rule_fallback() {
+        let content = r"<html><body><article><p>Text</p></article></body></html>";
+        let rule = SiteRule {
+            domain: "example.com".to_string(),
+            selector: Some("div.story".to_string()),
+            remove: Vec::new(),
+        };
+
+        assert_eq!(
+            extract_content(content, Some(&rule)).unwrap(),
+            "<article><p>Text</p></article>"
+        );
+    }
+}
@@ -0,0 +1,842 @@
+use std::{collections::HashMap, io::Write, str::FromStr};
+
+use chrono::{DateTime, FixedOffset};
+use quick_xml::{
+    escape::escape,
+    events::{BytesCData, BytesDecl, BytesEnd, BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache::{hash, now},
+    error::FurssError,
+    html::plain_text,
+};
+
+const JSON_FEED_VERSION: &str = "https://jsonfeed.org/version/1.1";
+const ATOM_NAMESPACE: &str = "http://www.w3.org/2005/Atom";
+pub const CONTENT_NAMESPACE: &str = "http://purl.org/rss/1.0/modules/content/";
+const DC_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+
+/// Format a feed is converted to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum FeedFormat {
+    /// RSS 2.0
+    Rss,
+    Atom,
+    /// JSON Feed 1.1
+    Json,
+}
+
+impl FromStr for FeedFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "rss" => Ok(Self::Rss),
+            "atom" => Ok(Self::Atom),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown feed format {format}")),
+        }
+    }
+}
+
+/// Formats are read case insensitively, `format=RSS` is `format=rss`
+impl TryFrom<String> for FeedFormat {
+    type Error = String;
+
+    fn try_from(format: String) -> Result<Self, Self::Error> {
+        format.parse()
+    }
+}
+
+impl FeedFormat {
+    #[must_use]
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Self::Rss => "application/rss+xml",
+            Self::Atom => "application/atom+xml",
+            Self::Json => "application/feed+json",
+        }
+    }
+
+    /// Format of a media type, parameters like `;q=0.9` are ignored
+    #[must_use]
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let media_type = content_type.split(';').next()?.trim();
+        [Self::Rss, Self::Atom, Self::Json]
+            .into_iter()
+            .find(|format| media_type.eq_ignore_ascii_case(format.content_type()))
+    }
+}
+
+/// Channel metadata and items of a feed, whatever its format
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Feed {
+    pub title: String,
+    pub link: Option<String>,
+    pub description: Option<String>,
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Item {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub link: Option<String>,
+    pub summary: Option<String>,
+    /// Html of the article
+    pub content: Option<String>,
+    pub published: Option<String>,
+    pub updated: Option<String>,
+    pub author: Option<String>,
+    /// Only set when the feed is previewed
+    pub preview: Option<Preview>,
+}
+
+/// The upstream description and content of an item next to its extracted article, written in
+/// the `_furss` extension of JSON Feed items
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Preview {
+    pub description: Option<String>,
+    pub content: Option<String>,
+    /// `None` when the article couldn't be extracted
+    pub article: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonFeed {
+    version: String,
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    home_page_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default)]
+    items: Vec<JsonItem>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct JsonItem {
+    #[serde(default)]
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_html: Option<String>,
+    #[serde(skip_serializing)]
+    content_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_published: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    authors: Vec<JsonAuthor>,
+    /// Only in JSON Feed 1.0
+    #[serde(skip_serializing)]
+    author: Option<JsonAuthor>,
+    #[serde(
+        rename = "_furss",
+        skip_deserializing,
+        skip_serializing_if = "Option::is_none"
+    )]
+    preview: Option<Preview>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonAuthor {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+/// Link of an item, Atom ids are often the url of the article when there is no link
+#[must_use]
+pub fn item_link(link: Option<String>, id: Option<String>) -> Option<String> {
+    link.or_else(|| id.filter(|id| id.starts_with("http")))
+}
+
+/// `value` if it's an IRI, as Atom ids must be, or a URN made from it
+fn atom_id(value: &str) -> String {
+    let is_iri = value.split_once(':').is_some_and(|(scheme, rest)| {
+        !rest.is_empty()
+            && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    });
+
+    if is_iri {
+        value.to_string()
+    } else {
+        format!("urn:furss:{}", hash(value))
+    }
+}
+
+/// Atom links are attributes, and an entry can have several of them
+#[must_use]
+pub fn is_atom_link(e: &BytesStart) -> bool {
+    attribute(e, "href").is_some()
+}
+
+/// The `href` of an Atom link, if it points to the article (`rel` defaults to `alternate`)
+#[must_use]
+pub fn atom_link(e: &BytesStart) -> Option<String> {
+    attribute(e, "href").filter(|_| attribute(e, "rel").is_none_or(|rel| rel == "alternate"))
+}
+
+fn attribute(e: &BytesStart, name: &str) -> Option<String> {
+    e.try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|attribute| attribute.unescape_value().ok())
+        .map(|value| value.trim().to_string())
+}
+
+/// Unescaped text and CDATA of `raw`, only inside the `only` child elements if given
+fn text(raw: &str, only: Option<&[u8]>) -> String {
+    let mut reader = Reader::from_str(raw);
+    let mut text = String::new();
+    let mut inside = only.is_none();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Text(e)) if inside => {
+                text.push_str(&e.unescape().unwrap_or_else(|_| String::from_utf8_lossy(&e)));
+            }
+            Ok(Event::CData(e)) if inside => text.push_str(&String::from_utf8_lossy(&e)),
+            Ok(Event::Start(e)) if only == Some(e.name().as_ref()) => inside = true,
+            Ok(Event::End(e)) if only == Some(e.name().as_ref()) => inside = false,
+            Ok(Event::Eof) => break,
+            Err(_) => return raw.trim().to_string(),
+            Ok(_) => (),
+        }
+    }
+
+    text.trim().to_string()
+}
+
+/// Html of an Atom text construct, which is plain text unless its `type` says otherwise
+fn atom_html(e: &BytesStart, raw: &str) -> String {
+    match attribute(e, "type").as_deref() {
+        Some("html") => text(raw, None),
+        Some("xhtml") => raw.trim().to_string(),
+        _ => escape(text(raw, None)).into_owned(),
+    }
+}
+
+/// Keeps the first non-empty value of a field
+fn set_once(field: &mut Option<String>, value: String) {
+    if field.is_none() && !value.is_empty() {
+        *field = Some(value);
+    }
+}
+
+/// Dates of RSS are RFC 2822 and dates of Atom are RFC 3339
+fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc2822(date)
+        .or_else(|_| DateTime::parse_from_rfc3339(date))
+        .ok()
+}
+
+fn rfc3339(date: &str) -> String {
+    parse_date(date).map_or_else(|| date.to_string(), |date| date.to_rfc3339())
+}
+
+fn rfc2822(date: &str) -> String {
+    parse_date(date).map_or_else(|| date.to_string(), |date| date.to_rfc2822())
+}
+
+/// Writes `html` in CDATA sections, as `]]>` can't be inside one
+///
+/// # Errors
+///
+/// Will return `Err` if the writer fails
+pub fn write_html<W: Write>(
+    writer: &mut Writer<W>,
+    start: BytesStart,
+    html: &str,
+) -> std::io::Result<()> {
+    let end = start.to_end().into_owned();
+
+    writer.write_event(Event::Start(start))?;
+    for cdata in BytesCData::escaped(html) {
+        writer.write_event(Event::CData(cdata))?;
+    }
+    writer.write_event(Event::End(end))
+}
+
+fn write_text<W: Write>(writer: &mut Writer<W>, name: &str, text: &str) -> std::io::Result<()> {
+    writer
+        .create_element(name)
+        .write_text_content(BytesText::new(text))?;
+    Ok(())
+}
+
+impl Item {
+    fn set(&mut self, e: &BytesStart, raw: &str) {
+        match e.name().as_ref() {
+            b"title" => set_once(&mut self.title, text(raw, None)),
+            b"link" if is_atom_link(e) => {
+                if let Some(link) = atom_link(e) {
+                    set_once(&mut self.link, link);
+                }
+            }
+            b"link" => set_once(&mut self.link, text(raw, None)),
+            b"guid" | b"id" => set_once(&mut self.id, text(raw, None)),
+            b"description" => set_once(&mut self.summary, text(raw, None)),
+            b"summary" => set_once(&mut self.summary, atom_html(e, raw)),
+            b"content" => set_once(&mut self.content, atom_html(e, raw)),
+            name if name.ends_with(b":encoded") => set_once(&mut self.content, text(raw, None)),
+            b"pubDate" | b"published" | b"dc:date" => {
+                set_once(&mut self.published, text(raw, None));
+            }
+            b"updated" => set_once(&mut self.updated, text(raw, None)),
+            b"author" => set_once(
+                &mut self.author,
+                if raw.contains("<name") {
+                    text(raw, Some(b"name"))
+                } else {
+                    text(raw, None)
+                },
+            ),
+            b"dc:creator" => set_once(&mut self.author, text(raw, None)),
+            _ => (),
+        }
+    }
+}
+
+impl Feed {
+    fn set(&mut self, e: &BytesStart, raw: &str) {
+        match e.name().as_ref() {
+            b"title" if self.title.is_empty() => self.title = text(raw, None),
+            b"link" if is_atom_link(e) => {
+                if let Some(link) = atom_link(e) {
+                    set_once(&mut self.link, link);
+                }
+            }
+            b"link" => set_once(&mut self.link, text(raw, None)),
+            b"description" | b"subtitle" => set_once(&mut self.description, text(raw, None)),
+            _ => (),
+        }
+    }
+
+    /// Reads an RSS or Atom feed
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the feed isn't valid xml
+    pub fn from_xml(content: &str) -> Result<Self, FurssError> {
+        let mut reader = Reader::from_str(content);
+        let mut feed = Self::default();
+        let mut item: Option<Item> = None;
+
+        loop {
+            let event = reader
+                .read_event()
+                .map_err(|error| FurssError::xml(&reader, error))?;
+            match event {
+                Event::Start(e) => match e.name().as_ref() {
+                    b"item" | b"entry" => item = Some(Item::default()),
+                    // Containers of the channel metadata and items
+                    b"rss" | b"channel" | b"feed" | b"rdf:RDF" if item.is_none() => (),
+                    _ => {
+                        let raw = reader
+                            .read_text(e.name())
+                            .map_err(|error| FurssError::xml(&reader, error))?;
+                        match &mut item {
+                            Some(item) => item.set(&e, &raw),
+                            None => feed.set(&e, &raw),
+                        }
+                    }
+                },
+                Event::Empty(e) => match &mut item {
+                    Some(item) => item.set(&e, ""),
+                    None => feed.set(&e, ""),
+                },
+                Event::End(e) if matches!(e.name().as_ref(), b"item" | b"entry") => {
+                    if let Some(mut item) = item.take() {
+                        item.link = item_link(item.link.take(), item.id.clone());
+                        feed.items.push(item);
+                    }
+                }
+                Event::Eof => break,
+                _ => (),
+            }
+        }
+
+        Ok(feed)
+    }
+
+    /// Reads a JSON Feed
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the feed isn't a valid JSON Feed
+    pub fn from_json(content: &str) -> Result<Self, serde_json::Error> {
+        let feed: JsonFeed = serde_json::from_str(content)?;
+
+        Ok(Self {
+            title: feed.title,
+            link: feed.home_page_url,
+            description: feed.description,
+            items: feed
+                .items
+                .into_iter()
+                .map(|item| Item {
+                    id: Some(item.id).filter(|id| !id.is_empty()),
+                    title: item.title,
+                    link: item.url,
+                    summary: item.summary,
+                    content: item
+                        .content_html
+                        .or_else(|| item.content_text.map(|text| escape(&text).into_owned())),
+                    published: item.date_published,
+                    updated: item.date_modified,
+                    author: item
+                        .authors
+                        .into_iter()
+                        .chain(item.author)
+                        .find_map(|author| author.name),
+                    preview: None,
+                })
+                .collect(),
+        })
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the feed can't be serialized
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        let feed = JsonFeed {
+            version: JSON_FEED_VERSION.to_string(),
+            title: self.title.clone(),
+            home_page_url: self.link.clone(),
+            description: self.description.clone(),
+            items: self
+                .items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| JsonItem {
+                    // Ids are required in JSON Feed
+                    id: item
+                        .id
+                        .clone()
+                        .or_else(|| item.link.clone())
+                        .unwrap_or_else(|| index.to_string()),
+                    url: item.link.clone(),
+                    title: item.title.clone(),
+                    // Items need a content, the summary is used when there is none.
+                    // Summaries are plain text in JSON Feed.
+                    summary: item
+                        .content
+                        .as_ref()
+                        .and(item.summary.as_deref())
+                        .map(plain_text),
+                    content_html: item.content.clone().or_else(|| item.summary.clone()),
+                    date_published: item.published.as_deref().map(rfc3339),
+                    date_modified: item.updated.as_deref().map(rfc3339),
+                    authors: item
+                        .author
+                        .iter()
+                        .map(|name| JsonAuthor {
+                            name: Some(name.clone()),
+                        })
+                        .collect(),
+                    preview: item.preview.clone(),
+                    ..JsonItem::default()
+                })
+                .collect(),
+        };
+
+        serde_json::to_string(&feed)
+    }
+
+    /// Urls of the articles of the items
+    #[must_use]
+    pub fn links(&self) -> Vec<String> {
+        self.items
+            .iter()
+            .filter_map(|item| item.link.clone())
+            .collect()
+    }
+
+    /// Sets the content of the items from the articles, items without an article are kept
+    /// as they are unless `drop_failed` is set
+    pub fn add_content(&mut self, articles: &HashMap<String, String>, drop_failed: bool) {
+        self.items.retain_mut(|item| {
+            let article = item.link.as_ref().and_then(|link| articles.get(link));
+            if let Some(article) = article {
+                item.content = Some(article.clone());
+            }
+            article.is_some() || !drop_failed
+        });
+    }
+
+    /// Keeps the upstream description and content of the items next to their article, before
+    /// the articles replace the content
+    pub fn add_preview(&mut self, articles: &HashMap<String, String>) {
+        for item in &mut self.items {
+            item.preview = Some(Preview {
+                description: item.summary.clone(),
+                content: item.content.clone(),
+                article: item
+                    .link
+                    .as_ref()
+                    .and_then(|link| articles.get(link))
+                    .cloned(),
+            });
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the feed can't be written
+    pub fn to_rss(&self) -> std::io::Result<String> {
+        let mut writer = Writer::new(Vec::new());
+        writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("utf-8"), None)))?;
+        writer.write_event(Event::Start(BytesStart::new("rss").with_attributes([
+            ("version", "2.0"),
+            ("xmlns:content", CONTENT_NAMESPACE),
+            ("xmlns:dc", DC_NAMESPACE),
+        ])))?;
+        writer.write_event(Event::Start(BytesStart::new("channel")))?;
+        write_text(&mut writer, "title", &self.title)?;
+        if let Some(link) = &self.link {
+            write_text(&mut writer, "link", link)?;
+        }
+        write_text(
+            &mut writer,
+            "description",
+            self.description.as_deref().unwrap_or_default(),
+        )?;
+
+        for item in &self.items {
+            writer.write_event(Event::Start(BytesStart::new("item")))?;
+            if let Some(title) = &item.title {
+                write_text(&mut writer, "title", title)?;
+            }
+            if let Some(link) = &item.link {
+                write_text(&mut writer, "link", link)?;
+            }
+            if let Some(id) = &item.id {
+                let guid = writer.create_element("guid");
+                let guid = if item.link.as_ref() == Some(id) {
+                    guid
+                } else {
+                    guid.with_attribute(("isPermaLink", "false"))
+                };
+                guid.write_text_content(BytesText::new(id))?;
+            }
+            if let Some(summary) = &item.summary {
+                write_html(&mut writer, BytesStart::new("description"), summary)?;
+            }
+            if let Some(content) = &item.content {
+                write_html(&mut writer, BytesStart::new("content:encoded"), content)?;
+            }
+            if let Some(date) = item.published.as_ref().or(item.updated.as_ref()) {
+                write_text(&mut writer, "pubDate", &rfc2822(date))?;
+            }
+            if let Some(author) = &item.author {
+                write_text(&mut writer, "dc:creator", author)?;
+            }
+            writer.write_event(Event::End(BytesEnd::new("item")))?;
+        }
+
+        writer.write_event(Event::End(BytesEnd::new("channel")))?;
+        writer.write_event(Event::End(BytesEnd::new("rss")))?;
+
+        Ok(String::from_utf8_lossy(&writer.into_inner()).into_owned())
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the feed can't be written
+    pub fn to_atom(&self) -> std::io::Result<String> {
+        // `updated` is required, the feed was updated with its last item
+        let now = DateTime::from_timestamp(i64::try_from(now()).unwrap_or_default(), 0)
+            .unwrap_or_default()
+            .fixed_offset();
+        let updated = self
+            .items
+            .iter()
+            .filter_map(|item| item.updated.as_ref().or(item.published.as_ref()))
+            .filter_map(|date| parse_date(date))
+            .max()
+            .unwrap_or(now)
+            .to_rfc3339();
+
+        let mut writer = Writer::new(Vec::new());
+        writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("utf-8"), None)))?;
+        writer.write_event(Event::Start(
+            BytesStart::new("feed").with_attributes([("xmlns", ATOM_NAMESPACE)]),
+        ))?;
+        write_text(&mut writer, "title", &self.title)?;
+        if let Some(link) = &self.link {
+            writer
+                .create_element("link")
+                .with_attributes([("rel", "alternate"), ("href", link.as_str())])
+                .write_empty()?;
+        }
+        if let Some(description) = &self.description {
+            write_text(&mut writer, "subtitle", description)?;
+        }
+        write_text(
+            &mut writer,
+            "id",
+            &atom_id(self.link.as_deref().unwrap_or(self.title.as_str())),
+        )?;
+        write_text(&mut writer, "updated", &updated)?;
+
+        for (index, item) in self.items.iter().enumerate() {
+            writer.write_event(Event::Start(BytesStart::new("entry")))?;
+            write_text(
+                &mut writer,
+                "title",
+                item.title.as_deref().unwrap_or_default(),
+            )?;
+            if let Some(link) = &item.link {
+                writer
+                    .create_element("link")
+                    .with_attributes([("rel", "alternate"), ("href", link.as_str())])
+                    .write_empty()?;
+            }
+            // Ids are required in Atom
+            let id = item
+                .id
+                .as_ref()
+                .or(item.link.as_ref())
+                .map_or_else(|| format!("{} {index}", self.title), Clone::clone);
+            write_text(&mut writer, "id", &atom_id(&id))?;
+            let date = item.updated.as_ref().or(item.published.as_ref());
+            write_text(
+                &mut writer,
+                "updated",
+                &date.map_or_else(|| updated.clone(), |date| rfc3339(date)),
+            )?;
+            if let Some(published) = &item.published {
+                write_text(&mut writer, "published", &rfc3339(published))?;
+            }
+            if let Some(author) = &item.author {
+                writer
+                    .create_element("author")
+                    .write_inner_content(|writer| write_text(writer, "name", author))?;
+            }
+            if let Some(summary) = &item.summary {
+                write_html(
+                    &mut writer,
+                    BytesStart::new("summary").with_attributes([("type", "html")]),
+                    summary,
+                )?;
+            }
+            if let Some(content) = &item.content {
+                write_html(
+                    &mut writer,
+                    BytesStart::new("content").with_attributes([("type", "html")]),
+                    content,
+                )?;
+            }
+            writer.write_event(Event::End(BytesEnd::new("entry")))?;
+        }
+
+        writer.write_event(Event::End(BytesEnd::new("feed")))?;
+
+        Ok(String::from_utf8_lossy(&writer.into_inner()).into_owned())
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the feed can't be written in `format`
+    pub fn render(&self, format: FeedFormat) -> Result<String, FurssError> {
+        match format {
+            FeedFormat::Rss => Ok(self.to_rss()?),
+            FeedFormat::Atom => Ok(self.to_atom()?),
+            FeedFormat::Json => Ok(self.to_json()?),
+        }
+    }
+}
+
+/// JSON Feeds are objects, RSS and Atom feeds are xml
+#[must_use]
+pub fn is_json(content: &str) -> bool {
+    content.trim_start().starts_with('{')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feed_format_is_case_insensitive() {
+        #[derive(Deserialize)]
+        struct Options {
+            format: FeedFormat,
+        }
+
+        for (format, expected) in [
+            ("rss", FeedFormat::Rss),
+            ("RSS", FeedFormat::Rss),
+            ("Atom", FeedFormat::Atom),
+            ("Json", FeedFormat::Json),
+        ] {
+            let options: Options = toml::from_str(&format!("format = \"{format}\"")).unwrap();
+            assert_eq!(options.format, expected);
+        }
+        assert!(toml::from_str::<Options>("format = \"yaml\"").is_err());
+    }
+
+    #[test]
+    fn test_feed_from_rss() {
+        let content = r#"<rss version="2.0" xmlns:dc="http://purl.org/dc/elements/1.1/"><channel><title>Test</title><link>https://test.com/</link><description>RSS to test</description><image><title>Logo</title></image><item><title>First &amp; article</title><link>https://example.org</link><description><![CDATA[<p>Summary</p>]]></description><pubDate>Sun, 26 May 2024 10:00:00 -0400</pubDate><dc:creator>martabal</dc:creator></item></channel></rss>"#;
+
+        assert_eq!(
+            Feed::from_xml(content).unwrap(),
+            Feed {
+                title: "Test".to_string(),
+                link: Some("https://test.com/".to_string()),
+                description: Some("RSS to test".to_string()),
+                items: vec![Item {
+                    title: Some("First & article".to_string()),
+                    link: Some("https://example.org".to_string()),
+                    summary: Some("<p>Summary</p>".to_string()),
+                    published: Some("Sun, 26 May 2024 10:00:00 -0400".to_string()),
+                    author: Some("martabal".to_string()),
+                    ..Item::default()
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn test_feed_from_atom() {
+        let content = r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>Test</title><link href="https://test.com/"/><entry><id>tag:test.com,2024:1</id><title>First article</title><link rel="replies" href="https://test.com/1/comments"/><link href="https://test.com/1"/><updated>2024-05-26T10:00:00Z</updated><author><name>martabal</name><email>a@test.com</email></author><summary>1 &lt; 2</summary></entry></feed>"#;
+
+        assert_eq!(
+            Feed::from_xml(content).unwrap().items,
+            vec![Item {
+                id: Some("tag:test.com,2024:1".to_string()),
+                title: Some("First article".to_string()),
+                link: Some("https://test.com/1".to_string()),
+                summary: Some("1 &lt; 2".to_string()),
+                updated: Some("2024-05-26T10:00:00Z".to_string()),
+                author: Some("martabal".to_string()),
+                ..Item::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_feed_to_json() {
+        let mut feed = Feed::from_json(r#"{"version":"https://jsonfeed.org/version/1.1","title":"Test","items":[{"id":"1","url":"https://example.org","content_text":"Summary","date_published":"2024-05-26T10:00:00-04:00","author":{"name":"martabal"}},{"id":"2","url":"https://not.in.hashmap.com"}]}"#).unwrap();
+        let articles = HashMap::from([(
+            "https://example.org".to_string(),
+            "<p>Content of example.org</p>".to_string(),
+        )]);
+        feed.add_content(&articles, true);
+
+        assert_eq!(
+            feed.to_json().unwrap(),
+            r#"{"version":"https://jsonfeed.org/version/1.1","title":"Test","items":[{"id":"1","url":"https://example.org","content_html":"<p>Content of example.org</p>","date_published":"2024-05-26T10:00:00-04:00","authors":[{"name":"martabal"}]}]}"#
+        );
+    }
+
+    #[test]
+    fn test_feed_to_json_preview() {
+        let mut feed = Feed::from_json(r#"{"version":"https://jsonfeed.org/version/1.1","title":"Test","items":[{"id":"1","url":"https://example.org","summary":"Summary","content_html":"<p>Teaser</p>"},{"id":"2","url":"https://not.in.hashmap.com"}]}"#).unwrap();
+        let articles = HashMap::from([(
+            "https://example.org".to_string(),
+            "<p>Content of example.org</p>".to_string(),
+        )]);
+        feed.add_preview(&articles);
+        feed.add_content(&articles, false);
+
+        assert_eq!(
+            feed.to_json().unwrap(),
+            r#"{"version":"https://jsonfeed.org/version/1.1","title":"Test","items":[{"id":"1","url":"https://example.org","summary":"Summary","content_html":"<p>Content of example.org</p>","_furss":{"description":"Summary","content":"<p>Teaser</p>","article":"<p>Content of example.org</p>"}},{"id":"2","url":"https://not.in.hashmap.com","_furss":{"description":null,"content":null,"article":null}}]}"#
+        );
+    }
+
+    #[test]
+    fn test_feed_to_json_summary_is_plain_text() {
+        let mut feed = test_feed();
+        feed.items[0].summary = Some("<p>1 &lt; 2 <b>and</b>\n 3</p>".to_string());
+
+        assert!(feed
+            .to_json()
+            .unwrap()
+            .contains(r#""summary":"1 < 2 and 3","content_html":"<p>Content of example.org</p>""#));
+    }
+
+    #[test]
+    fn test_atom_ids_are_iris() {
+        let mut feed = test_feed();
+        feed.link = None;
+        feed.items[0].id = None;
+        feed.items[0].link = None;
+        let atom = feed.to_atom().unwrap();
+
+        assert!(atom.contains(&format!("<id>urn:furss:{}</id>", hash("Test"))));
+        assert!(atom.contains(&format!("<id>urn:furss:{}</id>", hash("Test 0"))));
+        assert_eq!(atom_id("tag:test.com,2024:1"), "tag:test.com,2024:1");
+        assert_eq!(atom_id("https://test.com/1"), "https://test.com/1");
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(
+            rfc3339("Sun, 26 May 2024 10:00:00 -0400"),
+            "2024-05-26T10:00:00-04:00"
+        );
+        assert_eq!(rfc3339("yesterday"), "yesterday");
+    }
+
+    fn test_feed() -> Feed {
+        Feed {
+            title: "Test".to_string(),
+            link: Some("https://test.com/".to_string()),
+            description: None,
+            items: vec![Item {
+                id: Some("1".to_string()),
+                title: Some("First article".to_string()),
+                link: Some("https://example.org".to_string()),
+                content: Some("<p>Content of example.org</p>".to_string()),
+                published: Some("2024-05-26T10:00:00-04:00".to_string()),
+                author: Some("martabal".to_string()),
+                ..Item::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn test_feed_to_rss() {
+        let rss = test_feed().to_rss().unwrap();
+
+        assert_eq!(
+            rss,
+            r#"<?xml version="1.0" encoding="utf-8"?><rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/" xmlns:dc="http://purl.org/dc/elements/1.1/"><channel><title>Test</title><link>https://test.com/</link><description></description><item><title>First article</title><link>https://example.org</link><guid isPermaLink="false">1</guid><content:encoded><![CDATA[<p>Content of example.org</p>]]></content:encoded><pubDate>Sun, 26 May 2024 10:00:00 -0400</pubDate><dc:creator>martabal</dc:creator></item></channel></rss>"#
+        );
+        assert_eq!(
+            Feed::from_xml(&rss).unwrap().items[0].content,
+            test_feed().items[0].content
+        );
+    }
+
+    #[test]
+    fn test_feed_to_atom() {
+        let atom = test_feed().to_atom().unwrap();
+
+        assert_eq!(
+            atom,
+            r#"<?xml version="1.0" encoding="utf-8"?><feed xmlns="http://www.w3.org/2005/Atom"><title>Test</title><link rel="alternate" href="https://test.com/"/><id>https://test.com/</id><updated>2024-05-26T10:00:00-04:00</updated><entry><title>First article</title><link rel="alternate" href="https://example.org"/><id>urn:furss:af63ac4c86019afc</id><updated>2024-05-26T10:00:00-04:00</updated><published>2024-05-26T10:00:00-04:00</published><author><name>martabal</name></author><content type="html"><![CDATA[<p>Content of example.org</p>]]></content></entry></feed>"#
+        );
+        assert_eq!(
+            Feed::from_xml(&atom).unwrap().items[0].content,
+            test_feed().items[0].content
+        );
+    }
+}
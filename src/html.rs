@@ -0,0 +1,61 @@
+//! Helpers to write back html parsed with `tl`
+
+use std::borrow::Cow;
+
+use quick_xml::escape::unescape;
+
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements that have neither content nor a closing tag
+#[must_use]
+pub fn is_void(name: &str) -> bool {
+    VOID_ELEMENTS
+        .iter()
+        .any(|void| name.eq_ignore_ascii_case(void))
+}
+
+pub fn push_start_tag<'a>(
+    html: &mut String,
+    name: &str,
+    attributes: impl IntoIterator<Item = (Cow<'a, str>, Option<Cow<'a, str>>)>,
+) {
+    html.push('<');
+    html.push_str(name);
+    for (key, value) in attributes {
+        html.push(' ');
+        html.push_str(&key);
+        if let Some(value) = value {
+            html.push_str("=\"");
+            html.push_str(&value.replace('"', "&quot;"));
+            html.push('"');
+        }
+    }
+    html.push('>');
+}
+
+/// Text of `html` without its tags, with its whitespace collapsed
+#[must_use]
+pub fn plain_text(html: &str) -> String {
+    let text = tl::parse(html, tl::ParserOptions::default()).map_or_else(
+        |_| html.to_string(),
+        |dom| {
+            dom.children()
+                .iter()
+                .filter_map(|node| node.get(dom.parser()))
+                .map(|node| node.inner_text(dom.parser()))
+                .collect()
+        },
+    );
+    let text = unescape(&text).map_or_else(|_| text.clone(), Cow::into_owned);
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn push_end_tag(html: &mut String, name: &str) {
+    html.push_str("</");
+    html.push_str(name);
+    html.push('>');
+}
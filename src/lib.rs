@@ -1,13 +1,36 @@
-use std::{
-    collections::HashMap,
-    sync::{Arc, OnceLock},
-};
+use std::sync::OnceLock;
+#[cfg(feature = "proxy")]
+use std::{collections::HashMap, sync::Arc};
+
+use serde::{Deserialize, Deserializer};
 
-use serde::Deserialize;
-use tokio::sync::Mutex;
+use crate::feed::FeedFormat;
+
+#[cfg(feature = "proxy")]
+use {
+    crate::{
+        cache::ArticleCache, config::WatchedFile, refresh::Snapshot, routes::FeedVersion,
+        rules::SiteRules, subscriptions::Subscriptions,
+    },
+    tokio::sync::Mutex,
+};
 
+pub mod cache;
+pub mod cli;
+pub mod config;
+pub mod error;
+pub mod extract;
+pub mod feed;
+pub mod html;
+pub mod metrics;
 pub mod parse;
+#[cfg(feature = "proxy")]
+pub mod refresh;
+pub mod rewrite;
 pub mod routes;
+pub mod rules;
+pub mod sanitize;
+pub mod subscriptions;
 
 pub const APP_DEFAULT_PORT: u16 = 3000;
 pub static APP_NAME: OnceLock<String> = OnceLock::new();
@@ -15,25 +38,55 @@ pub static APP_VERSION: OnceLock<String> = OnceLock::new();
 #[cfg(feature = "proxy")]
 pub static APP_PORT: OnceLock<u16> = OnceLock::new();
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Default, Deserialize, Hash)]
 pub struct FurssOptions {
     flaresolverr: Option<String>,
-    _proxy: Option<String>,
-    _proxy_username: Option<String>,
-    _proxy_password: Option<String>,
-    #[cfg(feature = "proxy")]
-    _disable_cache: Option<bool>,
+    proxy: Option<String>,
+    proxy_username: Option<String>,
+    proxy_password: Option<String>,
+    disable_cache: Option<bool>,
     full: Option<bool>,
     number_items: Option<u16>,
+    drop_failed: Option<bool>,
+    format: Option<FeedFormat>,
+    /// Selector of the element holding the articles, overrides the site rules
+    selector: Option<String>,
+    /// Comma separated selectors of the elements removed from the articles, the feeds file
+    /// can also list them in an array like the rules file
+    #[serde(default, deserialize_with = "selectors")]
+    remove: Option<String>,
+    /// Writes the upstream description and content of the items next to their article, in a
+    /// JSON Feed
+    preview: Option<bool>,
 }
 
-#[cfg(feature = "proxy")]
-type Cache = Arc<Mutex<HashMap<String, Arc<Mutex<HashMap<String, String>>>>>>;
+/// Selectors given either in a comma separated string or in an array, as a comma separated string
+fn selectors<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Selectors {
+        String(String),
+        Array(Vec<String>),
+    }
+
+    Ok(
+        Option::<Selectors>::deserialize(deserializer)?.map(|selectors| match selectors {
+            Selectors::String(selectors) => selectors,
+            Selectors::Array(selectors) => selectors.join(", "),
+        }),
+    )
+}
 
 #[cfg(feature = "proxy")]
 #[derive(Clone)]
 pub struct AppState {
-    pub cache: Cache,
+    pub cache: Arc<ArticleCache>,
+    pub rules: Arc<WatchedFile<SiteRules>>,
+    pub subscriptions: Arc<WatchedFile<Subscriptions>>,
+    /// Subscriptions rendered in the background by the refresher
+    pub refreshed: Arc<Mutex<HashMap<String, Snapshot>>>,
+    /// Last version of each served feed, by upstream url and options
+    pub modified: Arc<Mutex<HashMap<String, FeedVersion>>>,
 }
 
 pub enum LogLevel {
@@ -55,7 +108,7 @@ macro_rules! log_message {
                 }
                 #[cfg(not(feature = "proxy"))]
                 {
-                    println!("[TRACE] {}", format!($($arg)*));
+                    eprintln!("[TRACE] {}", format!($($arg)*));
                 }
             },
             $crate::LogLevel::Debug => {
@@ -65,7 +118,7 @@ macro_rules! log_message {
                 }
                 #[cfg(not(feature = "proxy"))]
                 {
-                    println!("[INFO] {}", format!($($arg)*));
+                    eprintln!("[DEBUG] {}", format!($($arg)*));
                 }
             },
             $crate::LogLevel::Info => {
@@ -75,7 +128,7 @@ macro_rules! log_message {
                 }
                 #[cfg(not(feature = "proxy"))]
                 {
-                    println!("[INFO] {}", format!($($arg)*));
+                    eprintln!("[INFO] {}", format!($($arg)*));
                 }
             },
             $crate::LogLevel::Warn => {
@@ -85,7 +138,7 @@ macro_rules! log_message {
                 }
                 #[cfg(not(feature = "proxy"))]
                 {
-                    println!("[WARN] {}", format!($($arg)*));
+                    eprintln!("[WARN] {}", format!($($arg)*));
                 }
             },
             $crate::LogLevel::Error => {
@@ -95,7 +148,7 @@ macro_rules! log_message {
                 }
                 #[cfg(not(feature = "proxy"))]
                 {
-                    println!("[ERROR] {}", format!($($arg)*));
+                    eprintln!("[ERROR] {}", format!($($arg)*));
                 }
             },
         }
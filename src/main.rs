@@ -1,14 +1,16 @@
-use axum::handler::Handler;
-use furss::{APP_DEFAULT_PORT, APP_NAME, APP_VERSION};
-use tokio::sync::Mutex;
-
-use std::{collections::HashMap, env, sync::Arc};
+use furss::{
+    cli::{self, Args, Command},
+    log_message, LogLevel, APP_NAME, APP_VERSION,
+};
 
 #[cfg(feature = "proxy")]
 use {
     dotenvy::dotenv,
-    furss::{routes::handler, AppState, APP_PORT},
-    std::net::SocketAddr,
+    furss::{
+        cache::ArticleCache, config::WatchedFile, refresh, routes, AppState, APP_DEFAULT_PORT,
+        APP_PORT,
+    },
+    std::{env, net::SocketAddr, sync::Arc},
     tracing::{info, warn},
     tracing_subscriber::{filter::LevelFilter, EnvFilter},
 };
@@ -20,13 +22,14 @@ async fn main() {
         .set(env!("CARGO_PKG_VERSION").to_string())
         .unwrap();
 
+    let args: Args = argh::from_env();
+
     #[cfg(feature = "proxy")]
     dotenv().ok();
 
-    let log_level_str = std::env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string());
-
     #[cfg(feature = "proxy")]
     {
+        let log_level_str = env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string());
         let filter = EnvFilter::builder()
             .with_default_directive(LevelFilter::INFO.into())
             .from_env()
@@ -39,34 +42,65 @@ async fn main() {
 
         tracing_subscriber::fmt()
             .with_env_filter(filter)
+            .with_writer(std::io::stderr)
             .compact()
             .init();
+    }
+
+    match args.command {
+        Some(Command::Fetch(fetch_args)) => {
+            if let Err(e) = cli::fetch(&fetch_args).await {
+                log_message!(LogLevel::Error, "Error fetching {}: {e}", fetch_args.url);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(feature = "proxy")]
+        None => serve().await,
+        #[cfg(not(feature = "proxy"))]
+        None => {
+            log_message!(
+                LogLevel::Error,
+                "{} was built without the proxy feature, use `{} fetch <url>` instead",
+                APP_NAME.get().unwrap(),
+                APP_NAME.get().unwrap()
+            );
+            std::process::exit(1);
+        }
+    }
+}
 
-        let state = AppState {
-            cache: Arc::new(Mutex::new(HashMap::new())),
-        };
-        let app_port = env::var("APP_PORT").map_or_else(
-            |_| {
-                warn!(
-                    "Environment variable not found, defaulting to default ({})",
-                    APP_DEFAULT_PORT
-                );
+#[cfg(feature = "proxy")]
+async fn serve() {
+    let state = AppState {
+        cache: Arc::new(ArticleCache::from_env()),
+        rules: Arc::new(WatchedFile::from_env("RULES_FILE")),
+        subscriptions: Arc::new(WatchedFile::from_env("FEEDS_FILE")),
+        refreshed: Arc::default(),
+        modified: Arc::default(),
+    };
+    let app_port = env::var("APP_PORT").map_or_else(
+        |_| {
+            warn!(
+                "Environment variable not found, defaulting to default ({})",
                 APP_DEFAULT_PORT
-            },
-            |val| val.parse().expect("Provided port is not a valid u16"),
-        );
-        APP_PORT.set(app_port).unwrap();
+            );
+            APP_DEFAULT_PORT
+        },
+        |val| val.parse().expect("Provided port is not a valid u16"),
+    );
+    APP_PORT.set(app_port).unwrap();
 
-        let address = SocketAddr::from(([0, 0, 0, 0], *APP_PORT.get().unwrap()));
-        let listener = tokio::net::TcpListener::bind(address).await.unwrap();
+    let address = SocketAddr::from(([0, 0, 0, 0], *APP_PORT.get().unwrap()));
+    let listener = tokio::net::TcpListener::bind(address).await.unwrap();
 
-        info!(
-            "Starting {} version {}",
-            APP_NAME.get().unwrap(),
-            APP_VERSION.get().unwrap()
-        );
-        axum::serve(listener, handler.with_state(state).into_make_service())
-            .await
-            .unwrap();
-    }
+    info!(
+        "Starting {} version {}",
+        APP_NAME.get().unwrap(),
+        APP_VERSION.get().unwrap()
+    );
+    refresh::spawn(state.clone());
+
+    axum::serve(listener, routes::app(state).into_make_service())
+        .await
+        .unwrap();
 }
@@ -0,0 +1,210 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, OnceLock, PoisonError},
+    time::Duration,
+};
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Upper bounds of the buckets of the histograms, in seconds
+const BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+pub struct Metric {
+    name: &'static str,
+    help: &'static str,
+}
+
+pub const FEED_REQUESTS: Metric = Metric {
+    name: "furss_feed_requests_total",
+    help: "Feeds served, by outcome",
+};
+pub const FEED_FETCH_DURATION: Metric = Metric {
+    name: "furss_feed_fetch_duration_seconds",
+    help: "Time to download an upstream feed",
+};
+pub const ARTICLE_FETCH_DURATION: Metric = Metric {
+    name: "furss_article_fetch_duration_seconds",
+    help: "Time to download an article",
+};
+pub const ARTICLE_RESPONSES: Metric = Metric {
+    name: "furss_article_responses_total",
+    help: "Responses to article downloads, by status code",
+};
+pub const EXTRACTIONS: Metric = Metric {
+    name: "furss_extractions_total",
+    help: "Article extractions, by domain and outcome",
+};
+pub const CACHE_REQUESTS: Metric = Metric {
+    name: "furss_cache_requests_total",
+    help: "Articles looked up in the cache, by result",
+};
+pub const DOWNLOADED_BYTES: Metric = Metric {
+    name: "furss_downloaded_bytes_total",
+    help: "Bytes of the feeds and articles downloaded",
+};
+
+#[derive(Default)]
+struct Histogram {
+    /// Count of the observations of each bucket, not cumulative
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+enum Family {
+    Counter(BTreeMap<String, u64>),
+    Histogram(BTreeMap<String, Histogram>),
+}
+
+struct Entry {
+    help: &'static str,
+    family: Family,
+}
+
+/// Counters and histograms, written in the Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    entries: Mutex<BTreeMap<&'static str, Entry>>,
+}
+
+/// `name="value"` pairs, with the values escaped
+fn labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{name}=\"{value}\"")
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Labels of a sample, with `extra` after the labels of the series
+fn series(labels: &str, extra: &str) -> String {
+    match (labels.is_empty(), extra.is_empty()) {
+        (true, true) => String::new(),
+        (false, true) => format!("{{{labels}}}"),
+        (true, false) => format!("{{{extra}}}"),
+        (false, false) => format!("{{{labels},{extra}}}"),
+    }
+}
+
+impl Metrics {
+    pub fn global() -> &'static Self {
+        METRICS.get_or_init(Self::default)
+    }
+
+    pub fn increment(&self, metric: &Metric, labels: &[(&str, &str)]) {
+        self.add(metric, labels, 1);
+    }
+
+    /// Adds `value` to a counter
+    pub fn add(&self, metric: &Metric, labels: &[(&str, &str)], value: u64) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let entry = entries.entry(metric.name).or_insert_with(|| Entry {
+            help: metric.help,
+            family: Family::Counter(BTreeMap::new()),
+        });
+        if let Family::Counter(counters) = &mut entry.family {
+            *counters.entry(self::labels(labels)).or_default() += value;
+        }
+        drop(entries);
+    }
+
+    /// Records a duration in a histogram
+    pub fn observe(&self, metric: &Metric, labels: &[(&str, &str)], duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let entry = entries.entry(metric.name).or_insert_with(|| Entry {
+            help: metric.help,
+            family: Family::Histogram(BTreeMap::new()),
+        });
+        if let Family::Histogram(histograms) = &mut entry.family {
+            let histogram = histograms.entry(self::labels(labels)).or_default();
+            histogram.buckets.resize(BUCKETS.len(), 0);
+            if let Some(bucket) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+                histogram.buckets[bucket] += 1;
+            }
+            histogram.sum += seconds;
+            histogram.count += 1;
+        }
+        drop(entries);
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut text = String::new();
+
+        for (name, entry) in entries.iter() {
+            let _ = writeln!(text, "# HELP {name} {}", entry.help);
+            match &entry.family {
+                Family::Counter(counters) => {
+                    let _ = writeln!(text, "# TYPE {name} counter");
+                    for (labels, value) in counters {
+                        let _ = writeln!(text, "{name}{} {value}", series(labels, ""));
+                    }
+                }
+                Family::Histogram(histograms) => {
+                    let _ = writeln!(text, "# TYPE {name} histogram");
+                    for (labels, histogram) in histograms {
+                        let mut cumulative = 0;
+                        for (bound, count) in BUCKETS.iter().zip(&histogram.buckets) {
+                            cumulative += count;
+                            let le = format!("le=\"{bound}\"");
+                            let _ =
+                                writeln!(text, "{name}_bucket{} {cumulative}", series(labels, &le));
+                        }
+                        let inf = series(labels, "le=\"+Inf\"");
+                        let _ = writeln!(text, "{name}_bucket{inf} {}", histogram.count);
+                        let series = series(labels, "");
+                        let _ = writeln!(text, "{name}_sum{series} {}", histogram.sum);
+                        let _ = writeln!(text, "{name}_count{series} {}", histogram.count);
+                    }
+                }
+            }
+        }
+        drop(entries);
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.increment(
+            &EXTRACTIONS,
+            &[("domain", "example.org"), ("outcome", "success")],
+        );
+        metrics.increment(
+            &EXTRACTIONS,
+            &[("domain", "example.org"), ("outcome", "success")],
+        );
+        metrics.add(&DOWNLOADED_BYTES, &[], 512);
+        metrics.observe(&FEED_FETCH_DURATION, &[], Duration::from_millis(200));
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE furss_extractions_total counter\n"));
+        assert!(text
+            .contains("furss_extractions_total{domain=\"example.org\",outcome=\"success\"} 2\n"));
+        assert!(text.contains("furss_downloaded_bytes_total 512\n"));
+        assert!(text.contains("furss_feed_fetch_duration_seconds_bucket{le=\"0.1\"} 0\n"));
+        assert!(text.contains("furss_feed_fetch_duration_seconds_bucket{le=\"0.25\"} 1\n"));
+        assert!(text.contains("furss_feed_fetch_duration_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("furss_feed_fetch_duration_seconds_count 1\n"));
+    }
+
+    #[test]
+    fn test_labels_are_escaped() {
+        assert_eq!(labels(&[("domain", "a\"b\\c")]), "domain=\"a\\\"b\\\\c\"");
+    }
+}
@@ -1,9 +1,12 @@
 use core::str;
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet, VecDeque},
-    error::Error,
+    env,
     io::Cursor,
+    ops::Range,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 use futures::{stream, StreamExt};
@@ -12,160 +15,652 @@ use quick_xml::{
     Reader, Writer,
 };
 
-use reqwest::{Client, Url};
+use reqwest::{
+    header::{HeaderMap, HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    Client, Proxy, RequestBuilder, StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 
-use crate::{log_message, AppState, FurssOptions, LogLevel};
+use crate::{
+    cache::{normalize_url, now, ArticleCache, CachedArticle, CachedFeed},
+    error::FurssError,
+    extract::extract_content,
+    feed::{
+        atom_link, is_atom_link, is_json, item_link, write_html, Feed, FeedFormat,
+        CONTENT_NAMESPACE,
+    },
+    log_message,
+    metrics::{
+        Metric, Metrics, ARTICLE_FETCH_DURATION, ARTICLE_RESPONSES, CACHE_REQUESTS,
+        DOWNLOADED_BYTES, EXTRACTIONS, FEED_FETCH_DURATION,
+    },
+    rewrite::{absolutize, document_base},
+    rules::SiteRules,
+    sanitize::Sanitizer,
+    FurssOptions, LogLevel,
+};
 
 const ITEMS: &[&[u8]] = &[b"item", b"entry"];
-const LINKS: &[&[u8]] = &[b"link", b"id"];
+const LINK: &[u8] = b"link";
+/// Elements holding the id of an item in RSS and Atom
+const IDS: &[&[u8]] = &[b"guid", b"id"];
+/// Root element of Atom feeds, RSS feeds have `rss` or `rdf:RDF`
+const ATOM_ROOT: &[u8] = b"feed";
+/// Local names of the root elements of RSS 2.0, RSS 1.0 and Atom feeds
+const ROOTS: &[&[u8]] = &[b"rss", b"RDF", ATOM_ROOT];
+const REQUEST_DEFAULT_TIMEOUT: u64 = 30;
+const PROXY_SCHEMES: &[&str] = &["http", "https", "socks5", "socks5h"];
+/// `FlareSolverr` gives up on a challenge after its `maxTimeout`, 60 seconds by default
+const FLARESOLVERR_TIMEOUT: Duration = Duration::from_secs(90);
+
+#[derive(Serialize)]
+struct ProxySettings {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+}
+
+#[derive(Serialize)]
+struct FlaresolverrRequest<'a> {
+    cmd: &'a str,
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxy: Option<&'a ProxySettings>,
+}
+
+/// Answer of `FlareSolverr`, the page it downloaded is in `solution`
+#[derive(Deserialize)]
+struct FlaresolverrResponse {
+    status: String,
+    #[serde(default)]
+    message: String,
+    solution: Option<FlaresolverrSolution>,
+}
+
+#[derive(Deserialize)]
+struct FlaresolverrSolution {
+    status: u16,
+    response: String,
+}
+
+/// A feed with the articles, and the media type it is written in
+#[derive(Clone)]
+pub struct RenderedFeed {
+    pub body: String,
+    pub content_type: String,
+}
 
 #[must_use]
 pub fn add_http_prefix(mut url: &str) -> String {
     url = url.trim_start_matches('/');
-    if !url.starts_with("http://") && !url.starts_with("https://") {
-        format!("http://{url}")
-    } else {
-        url.to_string()
+    // Path normalization merges the slashes of the scheme, `https://` becomes `https:/`
+    for scheme in ["http:", "https:"] {
+        if let Some(rest) = url.strip_prefix(scheme) {
+            return format!("{scheme}//{}", rest.trim_start_matches('/'));
+        }
+    }
+    format!("http://{url}")
+}
+
+/// The proxy from the request options, or the one from the environment.
+/// Credentials are taken from the same place as the proxy url.
+fn proxy_settings(options: &FurssOptions) -> Option<ProxySettings> {
+    options.proxy.as_ref().map_or_else(
+        || {
+            env::var("PROXY").ok().map(|url| ProxySettings {
+                url,
+                username: env::var("PROXY_USERNAME").ok(),
+                password: env::var("PROXY_PASSWORD").ok(),
+            })
+        },
+        |url| {
+            Some(ProxySettings {
+                url: url.clone(),
+                username: options.proxy_username.clone(),
+                password: options.proxy_password.clone(),
+            })
+        },
+    )
+}
+
+/// Seconds to wait for the upstream, from `REQUEST_TIMEOUT`
+fn request_timeout() -> Duration {
+    Duration::from_secs(
+        env::var("REQUEST_TIMEOUT")
+            .ok()
+            .and_then(|timeout| timeout.parse().ok())
+            .unwrap_or(REQUEST_DEFAULT_TIMEOUT),
+    )
+}
+
+fn build_client(proxy: Option<&ProxySettings>) -> Result<Client, FurssError> {
+    let mut builder = Client::builder().timeout(request_timeout());
+    if let Some(settings) = proxy {
+        let proxy_url = Url::parse(&settings.url)
+            .map_err(|_| FurssError::InvalidOption(format!("Invalid proxy {}", settings.url)))?;
+        if !PROXY_SCHEMES.contains(&proxy_url.scheme()) {
+            return Err(FurssError::InvalidOption(format!(
+                "Unsupported proxy scheme {}",
+                proxy_url.scheme()
+            )));
+        }
+        let mut proxy = Proxy::all(proxy_url)?;
+        if let Some(username) = &settings.username {
+            proxy = proxy.basic_auth(username, settings.password.as_deref().unwrap_or_default());
+        }
+        builder = builder.proxy(proxy);
+    }
+
+    Ok(builder.build()?)
+}
+
+fn header_value(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string)
+}
+
+/// Format of an xml feed, from its root element
+fn xml_format(content: &str) -> FeedFormat {
+    let mut reader = Reader::from_str(content);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e) | Event::Empty(e)) if e.local_name().as_ref() == ATOM_ROOT => {
+                return FeedFormat::Atom;
+            }
+            Ok(Event::Start(_) | Event::Empty(_) | Event::Eof) | Err(_) => return FeedFormat::Rss,
+            _ => (),
+        }
     }
 }
 
-fn parse_rss_feed(content: &str) -> Vec<String> {
+fn parse_rss_feed(content: &str) -> Result<Vec<String>, FurssError> {
     let mut reader = Reader::from_str(content);
     let mut buf = Vec::new();
 
     let mut urls: Vec<String> = Vec::new();
+    let mut has_root = false;
     let mut in_item = false;
+    let mut link: Option<String> = None;
+    let mut id: Option<String> = None;
 
     loop {
-        match &reader.read_event_into(&mut buf) {
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-            Ok(Event::Eof) => break,
-            Ok(Event::Start(ref e)) => match e.name().as_ref() {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|error| FurssError::xml(&reader, error))?;
+        match &event {
+            Event::Start(ref e) | Event::Empty(ref e) if !has_root => {
+                if !ROOTS.contains(&e.local_name().as_ref()) {
+                    return Err(FurssError::NotAFeed);
+                }
+                has_root = true;
+            }
+            Event::Eof => break,
+            Event::Start(ref e) => match e.name().as_ref() {
                 name if ITEMS.contains(&name) => {
                     in_item = true;
+                    link = None;
+                    id = None;
+                }
+                LINK if in_item && link.is_none() => {
+                    link = if is_atom_link(e) {
+                        atom_link(e)
+                    } else {
+                        let link = reader
+                            .read_text(e.name())
+                            .map_err(|error| FurssError::xml(&reader, error))?;
+                        Some(link.trim().to_string())
+                    };
                 }
-                name if in_item && LINKS.contains(&name) => {
-                    let link = reader
+                name if in_item && id.is_none() && IDS.contains(&name) => {
+                    let text = reader
                         .read_text(e.name())
-                        .expect("Cannot decode text value");
-                    urls.push(link.to_string());
+                        .map_err(|error| FurssError::xml(&reader, error))?;
+                    id = Some(text.trim().to_string()).filter(|id| !id.is_empty());
                 }
                 _ => (),
             },
-            Ok(Event::End(ref e)) => {
-                if ITEMS.contains(&e.name().as_ref()) {
-                    in_item = false;
-                }
+            Event::Empty(ref e) if in_item && link.is_none() && e.name().as_ref() == LINK => {
+                link = atom_link(e);
+            }
+            Event::End(ref e) if ITEMS.contains(&e.name().as_ref()) => {
+                in_item = false;
+                // The same link as the item of the feed converted to another format
+                urls.extend(item_link(link.take(), id.take()));
             }
             _ => (),
         }
         buf.clear();
     }
 
-    urls
+    if has_root {
+        Ok(urls)
+    } else {
+        Err(FurssError::NotAFeed)
+    }
+}
+
+/// Element of an item the article is written to
+enum ContentElement {
+    /// `content:encoded` of RSS feeds, with the prefix bound to the content module
+    Encoded(String),
+    /// `<content type="html">` of Atom feeds
+    Atom,
+}
+
+impl ContentElement {
+    /// Declares the content module on the root element of RSS feeds if needed
+    fn from_root(root: &mut BytesStart) -> Self {
+        if root.local_name().as_ref() == ATOM_ROOT {
+            Self::Atom
+        } else {
+            Self::Encoded(format!("{}:encoded", declare_content_namespace(root)))
+        }
+    }
+
+    fn is(&self, name: &[u8]) -> bool {
+        self.name().as_bytes() == name
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Self::Encoded(name) => name,
+            Self::Atom => "content",
+        }
+    }
+
+    fn write<W: std::io::Write>(&self, writer: &mut Writer<W>, html: &str) -> std::io::Result<()> {
+        let start = match self {
+            Self::Encoded(name) => BytesStart::new(name.as_str()),
+            Self::Atom => BytesStart::new("content").with_attributes([("type", "html")]),
+        };
+
+        write_html(writer, start, html)
+    }
+}
+
+/// Returns the prefix bound to the content module on `root`, declaring it if there is none
+fn declare_content_namespace(root: &mut BytesStart) -> String {
+    let mut prefixes: HashSet<Vec<u8>> = HashSet::new();
+    for attribute in root.attributes().flatten() {
+        if let Some(prefix) = attribute.key.as_ref().strip_prefix(b"xmlns:") {
+            if attribute.value.as_ref() == CONTENT_NAMESPACE.as_bytes() {
+                return String::from_utf8_lossy(prefix).into_owned();
+            }
+            prefixes.insert(prefix.to_vec());
+        }
+    }
+
+    // Another namespace could already use the `content` prefix
+    let mut prefix = "content".to_string();
+    let mut i = 1;
+    while prefixes.contains(prefix.as_bytes()) {
+        prefix = format!("content{i}");
+        i += 1;
+    }
+    root.push_attribute((format!("xmlns:{prefix}").as_str(), CONTENT_NAMESPACE));
+
+    prefix
+}
+
+/// Events inside an item are buffered until the end of the item
+fn write_or_buffer<'a, W: std::io::Write>(
+    writer: &mut Writer<W>,
+    buffer: &mut VecDeque<Event<'a>>,
+    event: Event<'a>,
+) -> std::io::Result<()> {
+    if buffer.is_empty() {
+        writer.write_event(event)
+    } else {
+        buffer.push_back(event);
+        Ok(())
+    }
+}
+
+/// Writes the events of an item, the article replaces the `existing` content of the item
+fn write_item<W: std::io::Write>(
+    writer: &mut Writer<W>,
+    mut events: VecDeque<Event>,
+    existing: Option<Range<usize>>,
+    article: Option<(&String, &ContentElement)>,
+    end: BytesEnd,
+) -> std::io::Result<()> {
+    if let Some(existing) = existing.filter(|_| article.is_some()) {
+        events.drain(existing);
+    }
+    for event in events {
+        writer.write_event(event)?;
+    }
+    if let Some((review, element)) = article {
+        element.write(writer, review)?;
+    }
+    writer.write_event(Event::End(end))
 }
 
 fn add_content_to_item(
     content: &str,
     cache: &HashMap<String, String>,
-) -> Result<String, Box<dyn Error>> {
+    drop_failed: bool,
+) -> Result<String, FurssError> {
     let mut reader = Reader::from_str(content);
 
     let mut temp_content: VecDeque<Event> = VecDeque::new();
 
     let mut writer = Writer::new(Cursor::new(Vec::<u8>::new()));
     let mut url: String = String::new();
+    let mut id: Option<String> = None;
+    // Known once the root element is read
+    let mut element: Option<ContentElement> = None;
+    // Events of the content the item already has, replaced by the article
+    let mut existing: Option<Range<usize>> = None;
 
     loop {
         match reader.read_event() {
             Ok(Event::Start(e)) => match e.name().as_ref() {
+                // The root element, where the content namespace is declared
+                _ if element.is_none() => {
+                    let mut root = e.clone();
+                    element = Some(ContentElement::from_root(&mut root));
+                    writer.write_event(Event::Start(root))?;
+                }
                 name if ITEMS.contains(&name) => {
                     temp_content.push_back(Event::Start(e.clone()));
                 }
-                name if LINKS.contains(&name) => {
+                LINK => {
                     if temp_content.is_empty() {
-                        writer
-                            .write_event(Event::Start(e.clone()))
-                            .expect("Failed to write start tag");
+                        writer.write_event(Event::Start(e.clone()))?;
                     } else {
                         temp_content.push_back(Event::Start(e.clone()));
-                        let link = reader
-                            .clone()
-                            .read_text(e.name())
-                            .expect("Cannot decode text value");
-                        url = link.to_string();
+                        if url.is_empty() {
+                            url = if is_atom_link(&e) {
+                                atom_link(&e).unwrap_or_default()
+                            } else {
+                                let link = reader
+                                    .clone()
+                                    .read_text(e.name())
+                                    .map_err(|error| FurssError::xml(&reader, error))?;
+                                link.trim().to_string()
+                            };
+                        }
                     }
                 }
-
-                _ => {
-                    if temp_content.is_empty() {
-                        writer
-                            .write_event(Event::Start(e.clone()))
-                            .expect("Failed to write start tag");
-                    } else {
-                        temp_content.push_back(Event::Start(e.clone()));
-                    }
+                name if !temp_content.is_empty() && id.is_none() && IDS.contains(&name) => {
+                    temp_content.push_back(Event::Start(e.clone()));
+                    let text = reader
+                        .clone()
+                        .read_text(e.name())
+                        .map_err(|error| FurssError::xml(&reader, error))?;
+                    id = Some(text.trim().to_string()).filter(|id| !id.is_empty());
+                }
+                name if !temp_content.is_empty()
+                    && element.as_ref().is_some_and(|element| element.is(name)) =>
+                {
+                    let inner = reader
+                        .read_text(e.name())
+                        .map_err(|error| FurssError::xml(&reader, error))?;
+                    existing = Some(temp_content.len()..temp_content.len() + 3);
+                    temp_content.push_back(Event::Start(e.clone()));
+                    temp_content.push_back(Event::Text(BytesText::from_escaped(inner)));
+                    temp_content.push_back(Event::End(e.to_end().into_owned()));
                 }
+                _ => write_or_buffer(&mut writer, &mut temp_content, Event::Start(e.clone()))?,
             },
+            Ok(Event::Empty(e))
+                if !temp_content.is_empty()
+                    && element
+                        .as_ref()
+                        .is_some_and(|element| element.is(e.name().as_ref())) =>
+            {
+                existing = Some(temp_content.len()..temp_content.len() + 1);
+                temp_content.push_back(Event::Empty(e));
+            }
+            Ok(Event::Empty(e)) if !temp_content.is_empty() && e.name().as_ref() == LINK => {
+                if url.is_empty() {
+                    url = atom_link(&e).unwrap_or_default();
+                }
+                temp_content.push_back(Event::Empty(e));
+            }
             Ok(Event::End(ref e)) => {
                 if ITEMS.contains(&e.name().as_ref()) {
-                    if !url.is_empty() {
-                        let content = cache.get(&url).cloned();
-
-                        if let Some(review) = content {
-                            while let Some(event) = temp_content.pop_front() {
-                                writer.write_event(event).expect("Failed to write end tag");
-                            }
-                            writer
-                                .write_event(Event::Start(BytesStart::new("ns0:encoded")))
-                                .expect("Failed to write end tag");
-                            writer
-                                .write_event(Event::Text(BytesText::new(&review)))
-                                .expect("Failed to write end tag");
-                            writer
-                                .write_event(Event::End(BytesEnd::new("ns0:encoded")))
-                                .expect("Failed to write end tag");
-                            writer
-                                .write_event(Event::End(e.clone()))
-                                .expect("Failed to write end tag");
-                        }
-                        url = String::new();
+                    let link = item_link(Some(url).filter(|url| !url.is_empty()), id.take());
+                    let article = link.and_then(|link| cache.get(&link)).zip(element.as_ref());
+                    if article.is_some() || !drop_failed {
+                        write_item(
+                            &mut writer,
+                            std::mem::take(&mut temp_content),
+                            existing.take(),
+                            article,
+                            e.clone(),
+                        )?;
                     }
 
+                    url = String::new();
+                    existing = None;
                     temp_content = VecDeque::new();
-                } else if temp_content.is_empty() {
-                    writer
-                        .write_event(Event::End(e.clone()))
-                        .expect("Failed to write end tag");
                 } else {
-                    temp_content.push_back(Event::End(e.clone()));
+                    write_or_buffer(&mut writer, &mut temp_content, Event::End(e.clone()))?;
                 }
             }
             Ok(Event::Eof) => break,
-            Ok(e) => {
-                if temp_content.is_empty() {
-                    writer.write_event(e)?;
-                } else {
-                    temp_content.push_back(e);
-                }
-            }
+            Ok(e) => write_or_buffer(&mut writer, &mut temp_content, e)?,
 
-            Err(error) => panic!("Error at position {error}"),
+            Err(error) => return Err(FurssError::xml(&reader, error)),
         }
     }
-    Ok(String::from_utf8(writer.into_inner().into_inner())?)
+    Ok(String::from_utf8_lossy(&writer.into_inner().into_inner()).into_owned())
+}
+
+/// An article, or the cached article when it didn't change upstream
+enum Download {
+    Modified {
+        url: String,
+        etag: Option<String>,
+        last_modified: Option<String>,
+        body: Vec<u8>,
+    },
+    NotModified {
+        url: String,
+        article: CachedArticle,
+    },
+}
+
+/// Records the time since `started` in `metric`, and the bytes downloaded
+fn record_download(metric: &Metric, started: Instant, bytes: usize) {
+    let metrics = Metrics::global();
+    metrics.observe(metric, &[], started.elapsed());
+    metrics.add(&DOWNLOADED_BYTES, &[], bytes as u64);
+}
+
+fn record_cache_lookups(options: &FurssOptions, hits: usize, misses: usize) {
+    if options.disable_cache != Some(true) {
+        let metrics = Metrics::global();
+        metrics.add(&CACHE_REQUESTS, &[("result", "hit")], hits as u64);
+        metrics.add(&CACHE_REQUESTS, &[("result", "miss")], misses as u64);
+    }
+}
+
+/// Adds the validators of a cached response to `request`
+fn conditional(
+    request: RequestBuilder,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+) -> RequestBuilder {
+    let request = match etag {
+        Some(etag) => request.header(IF_NONE_MATCH, etag),
+        None => request,
+    };
+    match last_modified {
+        Some(last_modified) => request.header(IF_MODIFIED_SINCE, last_modified),
+        None => request,
+    }
+}
+
+async fn download_article(
+    client: &Client,
+    url: String,
+    stale: Option<&CachedArticle>,
+) -> Result<Download, FurssError> {
+    let mut request = client.get(&url);
+    if let Some(article) = stale {
+        request = conditional(
+            request,
+            article.etag.as_deref(),
+            article.last_modified.as_deref(),
+        );
+    }
+
+    let started = Instant::now();
+    let resp = request.send().await.inspect_err(|_| {
+        Metrics::global().increment(&ARTICLE_RESPONSES, &[("status", "error")]);
+    })?;
+    Metrics::global().increment(&ARTICLE_RESPONSES, &[("status", resp.status().as_str())]);
+    if let Some(article) = stale.filter(|_| resp.status() == StatusCode::NOT_MODIFIED) {
+        record_download(&ARTICLE_FETCH_DURATION, started, 0);
+        let mut article = article.clone();
+        article.fetched_at = now();
+        return Ok(Download::NotModified { url, article });
+    }
+
+    let resp = resp.error_for_status()?;
+    let etag = header_value(resp.headers(), ETAG);
+    let last_modified = header_value(resp.headers(), LAST_MODIFIED);
+    let body = resp.bytes().await?.to_vec();
+    record_download(&ARTICLE_FETCH_DURATION, started, body.len());
+    Ok(Download::Modified {
+        url,
+        etag,
+        last_modified,
+        body,
+    })
+}
+
+/// Downloads the feed, or reuses its cached body if it didn't change upstream
+async fn download_feed(
+    client: &Client,
+    url: Url,
+    cache: &ArticleCache,
+    options: &FurssOptions,
+) -> Result<String, FurssError> {
+    let cached = if options.disable_cache == Some(true) {
+        None
+    } else {
+        cache.get_feed(url.as_str()).await
+    };
+
+    let mut request = client.get(url.clone());
+    if let Some(feed) = &cached {
+        request = conditional(request, feed.etag.as_deref(), feed.last_modified.as_deref());
+    }
+
+    let started = Instant::now();
+    let resp = request.send().await?;
+    if let Some(mut feed) = cached.filter(|_| resp.status() == StatusCode::NOT_MODIFIED) {
+        log_message!(LogLevel::Debug, "NOT MODIFIED: {url}");
+        record_download(&FEED_FETCH_DURATION, started, 0);
+        let body = feed.body.clone();
+        feed.fetched_at = now();
+        cache.store_feed(url.as_str(), feed).await;
+        return Ok(body);
+    }
+
+    let resp = resp.error_for_status()?;
+    let etag = header_value(resp.headers(), ETAG);
+    let last_modified = header_value(resp.headers(), LAST_MODIFIED);
+    let body = resp.text().await?;
+    record_download(&FEED_FETCH_DURATION, started, body.len());
+    if etag.is_some() || last_modified.is_some() {
+        let feed = CachedFeed {
+            body: body.clone(),
+            fetched_at: now(),
+            etag,
+            last_modified,
+        };
+        cache.store_feed(url.as_str(), feed).await;
+    }
+
+    Ok(body)
+}
+
+/// Extracts the article of `page`, then makes it safe to embed in the feed
+fn process_article(url: &str, page: &str, rules: &SiteRules) -> Result<String, FurssError> {
+    let process = || {
+        let content = extract_content(page, rules.find(url))?;
+        let content = Sanitizer::global().sanitize(&content)?;
+
+        absolutize(&content, &document_base(page, url)?)
+    };
+
+    let content = process();
+    let domain = Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(ToString::to_string))
+        .unwrap_or_default();
+    let outcome = if content.is_ok() {
+        "success"
+    } else {
+        "failure"
+    };
+    Metrics::global().increment(&EXTRACTIONS, &[("domain", &domain), ("outcome", outcome)]);
+
+    content.map_err(|error| FurssError::Extraction {
+        url: url.to_string(),
+        message: error.to_string(),
+    })
+}
+
+/// Key of the articles of `feed_url` in the cache. Articles extracted with the selectors of
+/// the options are kept apart from the ones extracted with the site rules only.
+fn articles_key<'a>(feed_url: &'a str, options: &FurssOptions) -> Cow<'a, str> {
+    if options.selector.is_none() && options.remove.is_none() {
+        return Cow::Borrowed(feed_url);
+    }
+
+    Cow::Owned(format!(
+        "{feed_url}#selector={}&remove={}",
+        percent_encode(options.selector.as_deref().unwrap_or_default()),
+        percent_encode(options.remove.as_deref().unwrap_or_default())
+    ))
+}
+
+/// `value` with every byte but the unreserved characters of urls percent-encoded
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                char::from(byte).to_string()
+            } else {
+                format!("%{byte:02X}")
+            }
+        })
+        .collect()
 }
 
 async fn embellish_feed(
+    feed_url: &str,
     content: &str,
     options: &FurssOptions,
-    arc_cache: Arc<Mutex<HashMap<String, String>>>,
-) -> Result<String, Box<dyn Error>> {
-    let urls = parse_rss_feed(content);
+    client: &Client,
+    cache: &ArticleCache,
+    rules: &SiteRules,
+) -> Result<RenderedFeed, FurssError> {
+    let json_feed = if is_json(content) {
+        Some(Feed::from_json(content)?)
+    } else {
+        None
+    };
+    let urls = match &json_feed {
+        Some(feed) => feed.links(),
+        None => parse_rss_feed(content)?,
+    };
+    let feed_url = &*articles_key(feed_url, options);
 
     let mut url_requests: Vec<String> = match options.full {
-        Some(true) => urls,
+        Some(true) => urls.clone(),
         _ => urls
             .iter()
             .take(options.number_items.map_or(10, std::convert::Into::into))
@@ -173,42 +668,52 @@ async fn embellish_feed(
             .collect(),
     };
 
-    let mut articles: HashMap<String, String> = HashMap::new();
-
-    let mut cache = arc_cache.lock().await.clone();
-    for (key, value) in url_requests
-        .iter()
-        .filter_map(|key| cache.get(key).map(|value| (key.clone(), value.clone())))
-    {
-        articles.insert(key, value);
-    }
-
-    // Empty cache
-    url_requests.retain(|x| !cache.contains_key(x));
-
-    // Remove cached articles
-    let vec_set: HashSet<_> = url_requests.iter().cloned().collect();
-    cache.retain(|key, _| vec_set.contains(key));
+    let articles: HashMap<String, String> = if options.disable_cache == Some(true) {
+        HashMap::new()
+    } else {
+        cache
+            .get(feed_url, &url_requests)
+            .await
+            .into_iter()
+            .map(|(url, article)| (url, article.content))
+            .collect()
+    };
+    url_requests.retain(|url| !articles.contains_key(url));
+    record_cache_lookups(options, articles.len(), url_requests.len());
+    let stale: HashMap<String, CachedArticle> = if options.disable_cache == Some(true) {
+        HashMap::new()
+    } else {
+        cache.get_stale(feed_url, &url_requests).await
+    };
 
     let arc_articles: Arc<Mutex<HashMap<String, String>>> = Arc::new(Mutex::new(articles));
-
-    let client = Client::new();
+    let arc_fetched: Arc<Mutex<HashMap<String, CachedArticle>>> =
+        Arc::new(Mutex::new(HashMap::new()));
 
     let bodies = stream::iter(url_requests.clone())
         .map(|url| {
-            let client = &client;
-            async move {
-                let resp = client.get(&url).send().await?;
-                let body = resp.bytes().await?;
-                Ok::<_, reqwest::Error>((url, body))
-            }
+            let stale = stale.get(&url);
+            download_article(client, url, stale)
         })
         .buffer_unordered(8);
 
     bodies
         .for_each(|result| async {
             match result {
-                Ok((url, body)) => {
+                Ok(Download::NotModified { url, article }) => {
+                    log_message!(LogLevel::Trace, "NOT MODIFIED: {url}");
+                    arc_articles
+                        .lock()
+                        .await
+                        .insert(url.clone(), article.content.clone());
+                    arc_fetched.lock().await.insert(url, article);
+                }
+                Ok(Download::Modified {
+                    url,
+                    etag,
+                    last_modified,
+                    body,
+                }) => {
                     log_message!(
                         LogLevel::Trace,
                         "{}",
@@ -216,15 +721,19 @@ async fn embellish_feed(
                     );
 
                     match str::from_utf8(&body) {
-                        Ok(body_string) => {
-                            if let Ok(content) = extract_content(body_string) {
+                        Ok(body_string) => match process_article(&url, body_string, rules) {
+                            Ok(content) => {
                                 arc_articles
                                     .lock()
                                     .await
                                     .insert(url.clone(), content.clone());
-                                arc_cache.lock().await.insert(url, content);
+                                let mut article = CachedArticle::new(content);
+                                article.etag = etag;
+                                article.last_modified = last_modified;
+                                arc_fetched.lock().await.insert(url, article);
                             }
-                        }
+                            Err(e) => log_message!(LogLevel::Warn, "{e}"),
+                        },
                         Err(e) => {
                             log_message!(LogLevel::Warn, "ERROR converting {body:?} to utf8: {e}");
                         }
@@ -235,66 +744,115 @@ async fn embellish_feed(
         })
         .await;
 
-    let cloned_articles = arc_articles.lock().await.clone();
+    let fetched = arc_fetched.lock().await.clone();
+    cache.store(feed_url, &urls, fetched).await;
 
-    for (url, article) in cloned_articles.clone() {
-        cache.insert(url, article);
-    }
+    let cloned_articles = arc_articles.lock().await.clone();
 
-    add_content_to_item(content, &cloned_articles)
+    render_feed(content, json_feed, &cloned_articles, options)
 }
 
-fn extract_content(content: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
-    let dom = tl::parse(content, tl::ParserOptions::default())?;
-    let mut filtered_nodes = dom
-        .nodes()
-        .iter()
-        .filter(|node| node.as_tag().map_or(true, |tag| tag.name() != "script"));
+/// Writes the feed with its articles, in the format asked in the options
+fn render_feed(
+    content: &str,
+    json_feed: Option<Feed>,
+    articles: &HashMap<String, String>,
+    options: &FurssOptions,
+) -> Result<RenderedFeed, FurssError> {
+    let preview = options.preview == Some(true);
+    // Xml feeds are streamed as is unless they are converted to another format
+    if json_feed.is_none() && options.format.is_none() && !preview {
+        return Ok(RenderedFeed {
+            body: add_content_to_item(content, articles, options.drop_failed == Some(true))?,
+            content_type: xml_format(content).content_type().to_string(),
+        });
+    }
 
-    // Find the article tag among the filtered nodes
-    let article_node =
-        filtered_nodes.find(|node| node.as_tag().is_some_and(|tag| tag.name() == "article"));
+    let mut feed = match json_feed {
+        Some(feed) => feed,
+        None => Feed::from_xml(content)?,
+    };
+    if preview {
+        feed.add_preview(articles);
+    }
+    feed.add_content(articles, options.drop_failed == Some(true));
+    // Previews only fit in JSON Feed
+    let format = if preview {
+        FeedFormat::Json
+    } else {
+        options.format.unwrap_or(FeedFormat::Json)
+    };
 
-    // Convert the Option to Result, returning an error if the article_node is None
-    let article_tag = article_node
-        .ok_or("No article tag found")?
-        .as_tag()
-        .ok_or("Article node is not a tag")?;
+    Ok(RenderedFeed {
+        body: feed.render(format)?,
+        content_type: format.content_type().to_string(),
+    })
+}
 
-    let a = std::str::from_utf8(article_tag.raw().as_bytes())?.to_owned();
+/// The page `FlareSolverr` downloaded, from the JSON envelope of its answer
+fn flaresolverr_body(envelope: &str) -> Result<String, FurssError> {
+    let envelope: FlaresolverrResponse = serde_json::from_str(envelope)
+        .map_err(|error| FurssError::Flaresolverr(format!("invalid answer: {error}")))?;
+    if envelope.status != "ok" {
+        return Err(FurssError::Flaresolverr(envelope.message));
+    }
+    let solution = envelope
+        .solution
+        .ok_or_else(|| FurssError::Flaresolverr("answer without a solution".to_string()))?;
+    if let Some(status) = StatusCode::from_u16(solution.status)
+        .ok()
+        .filter(|status| status.is_client_error() || status.is_server_error())
+    {
+        return Err(FurssError::Upstream(status));
+    }
 
-    Ok(a)
+    Ok(solution.response)
 }
 
 /// # Errors
 ///
-/// Will panic if url is not a valid url
+/// Will return `Err` if the url isn't valid, if the feed can't be downloaded or if it isn't a
+/// valid feed
 pub async fn get_rss_feed(
     url: &str,
     options: &FurssOptions,
-    state: &AppState,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let mut rss_url = Url::parse(url)?;
-    rss_url.query_pairs_mut().clear();
+    cache: &ArticleCache,
+    rules: &SiteRules,
+) -> Result<RenderedFeed, FurssError> {
+    // The body and the articles of the feed are cached under its normalized url.
+    let feed_url = normalize_url(url);
+    let rss_url = Url::parse(&feed_url).map_err(|_| FurssError::InvalidUrl(url.to_string()))?;
+    let proxy = proxy_settings(options);
+    let client = build_client(proxy.as_ref())?;
     let body = match &options.flaresolverr {
         Some(flaresolverr_url) => {
-            let mut map = HashMap::new();
-            map.insert("cmd", "request.get");
-            map.insert("url", url);
-            let client = Client::new();
-            let response = client.post(flaresolverr_url).json(&map).send().await?;
+            // FlareSolverr downloads the feed itself, so it gets the proxy in the request.
+            // It's usually next to furss, so it's reached directly.
+            let request = FlaresolverrRequest {
+                cmd: "request.get",
+                url: &feed_url,
+                proxy: proxy.as_ref(),
+            };
+            let started = Instant::now();
+            let response = Client::builder()
+                .timeout(FLARESOLVERR_TIMEOUT)
+                .no_proxy()
+                .build()?
+                .post(flaresolverr_url)
+                .json(&request)
+                .send()
+                .await?
+                .error_for_status()?;
 
-            response.text().await?
+            let body = flaresolverr_body(&response.text().await?)?;
+            record_download(&FEED_FETCH_DURATION, started, body.len());
+            body
         }
-        None => reqwest::get(rss_url).await?.text().await?,
+        None => download_feed(&client, rss_url, cache, options).await?,
     };
 
-    let cache = state.cache.lock().await.get(url).map_or_else(
-        || Arc::new(Mutex::new(HashMap::<String, String>::new())),
-        std::clone::Clone::clone,
-    );
-
-    embellish_feed(&body, options, cache).await
+    let rules = rules.with_selectors(options.selector.as_deref(), options.remove.as_deref());
+    embellish_feed(&feed_url, &body, options, &client, cache, &rules).await
 }
 
 #[cfg(test)]
@@ -322,6 +880,13 @@ mod tests {
         assert_eq!(add_http_prefix(url), expected);
     }
 
+    #[test]
+    fn testadd_http_prefix_merged_slashes() {
+        let url = "/https:/example.com/feed?page=1";
+        let expected = "https://example.com/feed?page=1";
+        assert_eq!(add_http_prefix(url), expected);
+    }
+
     #[test]
     fn testadd_http_prefix_leading_slash() {
         let url = "/example.com";
@@ -358,39 +923,51 @@ mod tests {
     }
 
     #[test]
-    fn test_extract_content_without_script_tags() {
-        let content = r"<html><head><title>Test</title></head><body><article><h1>Article Title</h1><p>Article content goes here.</p></article></body></html>";
+    fn test_proxy_settings_from_options() {
+        let options = FurssOptions {
+            proxy: Some("socks5://127.0.0.1:1080".to_string()),
+            proxy_username: Some("user".to_string()),
+            ..Default::default()
+        };
 
-        assert_eq!(
-            extract_content(content).unwrap(),
-            "<article><h1>Article Title</h1><p>Article content goes here.</p></article>"
-        );
+        let settings = proxy_settings(&options).unwrap();
+        assert_eq!(settings.url, "socks5://127.0.0.1:1080");
+        assert_eq!(settings.username.as_deref(), Some("user"));
+        assert_eq!(settings.password, None);
     }
 
     #[test]
-    fn test_extract_content_with_script_tags() {
-        let content = r#"<html><head><title>Test</title></head><body><script>console.log("This is a script")<script><article><h1>Article Title</h1><p>Article content goes here.</p></article></body></html>"#;
+    fn test_build_client_with_proxy() {
+        let settings = ProxySettings {
+            url: "http://127.0.0.1:3128".to_string(),
+            username: Some("user".to_string()),
+            password: Some("password".to_string()),
+        };
 
-        assert_eq!(
-            extract_content(content).unwrap(),
-            "<article><h1>Article Title</h1><p>Article content goes here.</p></article>"
-        );
+        assert!(build_client(Some(&settings)).is_ok());
     }
 
     #[test]
-    fn test_extract_content_no_article_tag() {
-        let content = r"<html><head><title>Test</title></head><body><div><h1>Another Title</h1><p>Some content</p></div></body><html>";
+    fn test_build_client_unsupported_proxy_scheme() {
+        let settings = ProxySettings {
+            url: "socks4://127.0.0.1:1080".to_string(),
+            username: Some("user".to_string()),
+            password: None,
+        };
 
-        let result = extract_content(content);
+        let result = build_client(Some(&settings));
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err().to_string(), "No article tag found");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Invalid option: Unsupported proxy scheme socks4"
+        );
     }
 
+    const TEST_FEED: &str = r#"<rss version="2.0"><channel><title>Test</title><link>https://test.com/</link><description>RSS to test</description><language>en-us</language><item><title>First article</title><link>https://example.org</link><description>This is the description of example.org</description><pubDate>Sun, 26 May 2024 10:00:00 -0400</pubDate><dc:creator>martabal</dc:creator></item><item><title>Second article</title><link>https://not.in.hashmap.com</link><description>This is the description of not.in.hashmap.com</description><pubDate>Sun, 26 May 2024 09:00:00 -0400</pubDate><dc:creator>martabal</dc:creator></item></channel></rss>"#;
+
     #[test]
     fn test_add_content_to_item() {
-        let content = r#"<rss version="2.0"><channel><title>Test</title><link>https://test.com/</link><description>RSS to test</description><language>en-us</language><item><title>First article</title><link>https://example.org</link><description>This is the description of example.org</description><pubDate>Sun, 26 May 2024 10:00:00 -0400</pubDate><dc:creator>martabal</dc:creator></item><item><title>Second article</title><link>https://not.in.hashmap.com</link><description>This is the description of not.in.hashmap.com</description><pubDate>Sun, 26 May 2024 09:00:00 -0400</pubDate><dc:creator>martabal</dc:creator></item></channel></rss>"#;
-
-        let expect = r#"<rss version="2.0"><channel><title>Test</title><link>https://test.com/</link><description>RSS to test</description><language>en-us</language><item><title>First article</title><link>https://example.org</link><description>This is the description of example.org</description><pubDate>Sun, 26 May 2024 10:00:00 -0400</pubDate><dc:creator>martabal</dc:creator><ns0:encoded>Content of example.org</ns0:encoded></item></channel></rss>"#;
+        let expect = r#"<rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/"><channel><title>Test</title><link>https://test.com/</link><description>RSS to test</description><language>en-us</language><item><title>First article</title><link>https://example.org</link><description>This is the description of example.org</description><pubDate>Sun, 26 May 2024 10:00:00 -0400</pubDate><dc:creator>martabal</dc:creator><content:encoded><![CDATA[Content of example.org]]></content:encoded></item><item><title>Second article</title><link>https://not.in.hashmap.com</link><description>This is the description of not.in.hashmap.com</description><pubDate>Sun, 26 May 2024 09:00:00 -0400</pubDate><dc:creator>martabal</dc:creator></item></channel></rss>"#;
         let mut cache = HashMap::new();
 
         // Review some books.
@@ -398,6 +975,141 @@ mod tests {
             "https://example.org".to_string(),
             "Content of example.org".to_string(),
         );
-        assert_eq!(add_content_to_item(content, &cache).unwrap(), expect);
+        assert_eq!(
+            add_content_to_item(TEST_FEED, &cache, false).unwrap(),
+            expect
+        );
+    }
+
+    #[test]
+    fn test_add_content_to_item_drop_failed() {
+        let expect = r#"<rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/"><channel><title>Test</title><link>https://test.com/</link><description>RSS to test</description><language>en-us</language><item><title>First article</title><link>https://example.org</link><description>This is the description of example.org</description><pubDate>Sun, 26 May 2024 10:00:00 -0400</pubDate><dc:creator>martabal</dc:creator><content:encoded><![CDATA[Content of example.org]]></content:encoded></item></channel></rss>"#;
+        let mut cache = HashMap::new();
+        cache.insert(
+            "https://example.org".to_string(),
+            "Content of example.org".to_string(),
+        );
+        assert_eq!(
+            add_content_to_item(TEST_FEED, &cache, true).unwrap(),
+            expect
+        );
+    }
+
+    #[test]
+    fn test_add_content_to_item_existing_namespace() {
+        let content = r#"<rss version="2.0" xmlns:c="http://purl.org/rss/1.0/modules/content/"><channel><item><link>https://example.org</link><c:encoded><![CDATA[Summary]]></c:encoded></item><item><link>https://failed.org</link><c:encoded><![CDATA[<p>Summary</p>]]></c:encoded></item></channel></rss>"#;
+
+        let expect = r#"<rss version="2.0" xmlns:c="http://purl.org/rss/1.0/modules/content/"><channel><item><link>https://example.org</link><c:encoded><![CDATA[Content with ]]]]><![CDATA[> in it]]></c:encoded></item><item><link>https://failed.org</link><c:encoded><![CDATA[<p>Summary</p>]]></c:encoded></item></channel></rss>"#;
+        let mut cache = HashMap::new();
+        cache.insert(
+            "https://example.org".to_string(),
+            "Content with ]]> in it".to_string(),
+        );
+        assert_eq!(add_content_to_item(content, &cache, false).unwrap(), expect);
+    }
+
+    #[test]
+    fn test_parse_atom_feed() {
+        let content = r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>Test</title><link href="https://test.com/" rel="alternate"/><entry><id>tag:test.com,2024:1</id><link rel="replies" href="https://test.com/1/comments"/><link href="https://test.com/1"/></entry><entry><id>tag:test.com,2024:2</id><link rel="alternate" type="text/html" href="https://test.com/2"></link></entry></feed>"#;
+
+        assert_eq!(
+            parse_rss_feed(content).unwrap(),
+            vec!["https://test.com/1", "https://test.com/2"]
+        );
+        assert_eq!(xml_format(content), FeedFormat::Atom);
+        assert_eq!(xml_format(TEST_FEED), FeedFormat::Rss);
+    }
+
+    #[test]
+    fn test_atom_entry_without_link() {
+        let content = r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>Test</title><entry><id>https://example.org</id><title>First</title></entry><entry><id>tag:example.org,2024:2</id></entry></feed>"#;
+
+        assert_eq!(
+            parse_rss_feed(content).unwrap(),
+            vec!["https://example.org"]
+        );
+        assert_eq!(
+            Feed::from_xml(content).unwrap().links(),
+            parse_rss_feed(content).unwrap()
+        );
+
+        let cache = HashMap::from([(
+            "https://example.org".to_string(),
+            "<p>Content of example.org</p>".to_string(),
+        )]);
+        let expect = r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>Test</title><entry><id>https://example.org</id><title>First</title><content type="html"><![CDATA[<p>Content of example.org</p>]]></content></entry></feed>"#;
+        assert_eq!(add_content_to_item(content, &cache, true).unwrap(), expect);
+    }
+
+    #[test]
+    fn test_add_content_to_atom_entry() {
+        let content = r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>Test</title><entry><id>tag:example.org,2024:1</id><link rel="alternate" href="https://example.org"/><summary>Summary</summary><content type="text">Summary</content></entry></feed>"#;
+
+        let expect = r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>Test</title><entry><id>tag:example.org,2024:1</id><link rel="alternate" href="https://example.org"/><summary>Summary</summary><content type="html"><![CDATA[<p>Content of example.org</p>]]></content></entry></feed>"#;
+        let mut cache = HashMap::new();
+        cache.insert(
+            "https://example.org".to_string(),
+            "<p>Content of example.org</p>".to_string(),
+        );
+        assert_eq!(add_content_to_item(content, &cache, false).unwrap(), expect);
+    }
+
+    #[test]
+    fn test_parse_rss_feed_errors() {
+        assert!(matches!(
+            parse_rss_feed("<html><body>Not a feed</body></html>"),
+            Err(FurssError::NotAFeed)
+        ));
+        assert!(matches!(
+            parse_rss_feed("<rss><channel><item></channel></rss>"),
+            Err(FurssError::Xml { .. })
+        ));
+    }
+
+    #[test]
+    fn test_flaresolverr_body() {
+        let ok = r#"{"status":"ok","message":"Challenge not detected!","solution":{"url":"https://example.com/feed.xml","status":200,"response":"<rss></rss>"}}"#;
+        assert_eq!(flaresolverr_body(ok).unwrap(), "<rss></rss>");
+
+        let error = r#"{"status":"error","message":"Error solving the challenge"}"#;
+        assert!(matches!(
+            flaresolverr_body(error),
+            Err(FurssError::Flaresolverr(message)) if message == "Error solving the challenge"
+        ));
+        let not_found = r#"{"status":"ok","message":"","solution":{"status":404,"response":""}}"#;
+        assert!(matches!(
+            flaresolverr_body(not_found),
+            Err(FurssError::Upstream(StatusCode::NOT_FOUND))
+        ));
+        assert!(matches!(
+            flaresolverr_body("<rss></rss>"),
+            Err(FurssError::Flaresolverr(_))
+        ));
+    }
+
+    #[test]
+    fn test_articles_key() {
+        let url = "https://example.com/feed.xml";
+        assert_eq!(articles_key(url, &FurssOptions::default()), url);
+
+        let options: FurssOptions = toml::from_str("selector = \"main\"").unwrap();
+        assert_eq!(
+            articles_key(url, &options),
+            "https://example.com/feed.xml#selector=main&remove="
+        );
+
+        // A selector can't pass for another option
+        let options: FurssOptions = toml::from_str("selector = \"a&remove=b\"").unwrap();
+        let other: FurssOptions = toml::from_str("selector = \"a\"\nremove = \"b\"").unwrap();
+        assert_ne!(articles_key(url, &options), articles_key(url, &other));
+        assert_eq!(
+            articles_key(url, &other),
+            "https://example.com/feed.xml#selector=a&remove=b"
+        );
+        let options: FurssOptions = toml::from_str("remove = \".ad, aside\"").unwrap();
+        assert_eq!(
+            articles_key(url, &options),
+            "https://example.com/feed.xml#selector=&remove=.ad%2C%20aside"
+        );
     }
 }
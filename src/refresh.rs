@@ -0,0 +1,200 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    env,
+    hash::{Hash, Hasher},
+    time::Duration,
+};
+
+use crate::{
+    cache::now,
+    log_message,
+    parse::{get_rss_feed, RenderedFeed},
+    subscriptions::Subscription,
+    AppState, LogLevel,
+};
+
+const DEFAULT_REFRESH_INTERVAL: u64 = 15 * 60;
+
+/// Seconds between two refreshes of the subscriptions, from `REFRESH_INTERVAL`.
+/// `0` disables the refresher.
+fn refresh_interval() -> Option<Duration> {
+    let seconds = env::var("REFRESH_INTERVAL")
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(DEFAULT_REFRESH_INTERVAL);
+
+    (seconds > 0).then(|| Duration::from_secs(seconds))
+}
+
+/// A feed rendered by the refresher
+#[derive(Clone)]
+pub struct Snapshot {
+    /// Hash of the subscription the feed was rendered from
+    definition: u64,
+    rendered_at: u64,
+    feed: RenderedFeed,
+}
+
+fn definition(subscription: &Subscription) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    subscription.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Snapshot {
+    fn new(subscription: &Subscription, feed: RenderedFeed) -> Self {
+        Self {
+            definition: definition(subscription),
+            rendered_at: now(),
+            feed,
+        }
+    }
+
+    /// Whether the feed was rendered from `subscription` as it's defined now, less than two
+    /// refresh intervals ago, so a feed whose refreshes keep failing isn't served forever
+    fn is_current(&self, subscription: &Subscription, now: u64) -> bool {
+        let max_age = refresh_interval().map_or(0, |interval| 2 * interval.as_secs());
+
+        self.definition == definition(subscription)
+            && now.saturating_sub(self.rendered_at) < max_age
+    }
+}
+
+/// The feed of the subscription `name` rendered by the refresher, if it's still current
+pub async fn snapshot(
+    state: &AppState,
+    name: &str,
+    subscription: &Subscription,
+) -> Option<RenderedFeed> {
+    let refreshed = state.refreshed.lock().await;
+    let snapshot = refreshed
+        .get(name)
+        .filter(|snapshot| snapshot.is_current(subscription, now()))
+        .map(|snapshot| snapshot.feed.clone());
+    drop(refreshed);
+
+    snapshot
+}
+
+/// Fetches every subscription, extracting its new articles into the cache, and keeps the
+/// rendered feeds for the readers.
+///
+/// A feed that fails keeps its previous rendering, until it's too old or its subscription
+/// changes.
+pub async fn refresh(state: &AppState) {
+    let subscriptions = state.subscriptions.get().await;
+    let rules = state.rules.get().await;
+
+    for (name, subscription) in &subscriptions.feeds {
+        match get_rss_feed(
+            &subscription.url,
+            &subscription.options,
+            &state.cache,
+            &rules,
+        )
+        .await
+        {
+            Ok(feed) => {
+                let snapshot = Snapshot::new(subscription, feed);
+                state.refreshed.lock().await.insert(name.clone(), snapshot);
+            }
+            Err(e) => log_message!(LogLevel::Warn, "ERROR refreshing {name}: {e}"),
+        }
+    }
+
+    state.refreshed.lock().await.retain(|name, snapshot| {
+        subscriptions
+            .feeds
+            .get(name)
+            .is_some_and(|subscription| snapshot.is_current(subscription, now()))
+    });
+}
+
+/// Refreshes the subscriptions every `REFRESH_INTERVAL` seconds, starting right away
+pub fn spawn(state: AppState) {
+    let Some(period) = refresh_interval() else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            refresh(&state).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, sync::Arc};
+
+    use axum::{routing::get, Router};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::config::WatchedFile;
+
+    const FEED: &str = r#"<rss version="2.0"><channel><title>Test</title><link>https://example.com/</link><description>Feed to test</description><item><title>First article</title><link>http://127.0.0.1:1/article</link><description>Summary</description></item></channel></rss>"#;
+
+    fn subscription(url: &str) -> Subscription {
+        toml::from_str(&format!("url = \"{url}\"")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_refresh_then_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/feed.xml", listener.local_addr().unwrap());
+        let app = Router::new().route("/feed.xml", get(|| async { FEED }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let path = env::temp_dir().join(format!("furss-refresh-{}.toml", std::process::id()));
+        std::fs::write(&path, format!("[feeds.test]\nurl = \"{url}\"\n")).unwrap();
+        let state = AppState {
+            cache: Arc::default(),
+            rules: Arc::new(WatchedFile::new(None)),
+            subscriptions: Arc::new(WatchedFile::new(Some(path.clone()))),
+            refreshed: Arc::default(),
+            modified: Arc::default(),
+        };
+
+        refresh(&state).await;
+        let subscriptions = state.subscriptions.get().await;
+        let feed = snapshot(&state, "test", &subscriptions.feeds["test"])
+            .await
+            .unwrap();
+        assert!(feed.body.contains("First article"));
+        assert!(snapshot(
+            &state,
+            "test",
+            &subscription("https://example.com/feed.xml")
+        )
+        .await
+        .is_none());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_is_invalidated() {
+        let subscription = subscription("https://example.com/feed.xml");
+        let feed = RenderedFeed {
+            body: String::new(),
+            content_type: String::new(),
+        };
+        let snapshot = Snapshot::new(&subscription, feed);
+        let now = snapshot.rendered_at;
+        assert!(snapshot.is_current(&subscription, now));
+
+        let mut full = subscription.clone();
+        full.options = toml::from_str("full = true").unwrap();
+        assert!(!snapshot.is_current(&full, now));
+        let mut moved = subscription.clone();
+        moved.url = "https://example.org/feed.xml".to_string();
+        assert!(!snapshot.is_current(&moved, now));
+
+        let max_age = 2 * DEFAULT_REFRESH_INTERVAL;
+        assert!(snapshot.is_current(&subscription, now + max_age - 1));
+        assert!(!snapshot.is_current(&subscription, now + max_age));
+    }
+}
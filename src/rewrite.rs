@@ -0,0 +1,115 @@
+use std::error::Error;
+
+use reqwest::Url;
+use tl::Node;
+
+const URL_ATTRIBUTES: &[&str] = &["href", "src", "poster", "cite"];
+
+/// The url relative links of `page` are resolved against: its `<base href>` if any, else `url`
+///
+/// # Errors
+///
+/// Will return `Err` if `url` isn't a valid url or if the page can't be parsed
+pub fn document_base(page: &str, url: &str) -> Result<Url, Box<dyn Error + Send + Sync>> {
+    let url = Url::parse(url)?;
+    let dom = tl::parse(page, tl::ParserOptions::default())?;
+
+    let base = dom
+        .query_selector("base[href]")
+        .and_then(|mut handles| handles.next())
+        .and_then(|handle| handle.get(dom.parser()))
+        .and_then(Node::as_tag)
+        .and_then(|tag| tag.attributes().get("href").flatten())
+        .and_then(|href| url.join(href.as_utf8_str().trim()).ok());
+
+    Ok(base.unwrap_or(url))
+}
+
+fn absolute_url(base: &Url, value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() || value.starts_with('#') {
+        return None;
+    }
+
+    base.join(value).ok().map(String::from)
+}
+
+/// `srcset` is a list of `url descriptor`, separated by commas
+fn absolute_srcset(base: &Url, value: &str) -> String {
+    value
+        .split(',')
+        .map(|candidate| {
+            let candidate = candidate.trim();
+            let (url, descriptor) = candidate
+                .split_once(char::is_whitespace)
+                .unwrap_or((candidate, ""));
+            let url = absolute_url(base, url).unwrap_or_else(|| url.to_string());
+            if descriptor.is_empty() {
+                url
+            } else {
+                format!("{url} {}", descriptor.trim())
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Rewrites the relative urls of `html` into absolute urls
+///
+/// # Errors
+///
+/// Will return `Err` if the html can't be parsed
+pub fn absolutize(html: &str, base: &Url) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let mut dom = tl::parse(html, tl::ParserOptions::default())?;
+
+    for tag in dom.nodes_mut().iter_mut().filter_map(Node::as_tag_mut) {
+        let attributes = tag.attributes_mut();
+        for name in URL_ATTRIBUTES {
+            if let Some(Some(value)) = attributes.get_mut(*name) {
+                if let Some(url) = absolute_url(base, &value.as_utf8_str()) {
+                    value.set(url)?;
+                }
+            }
+        }
+        if let Some(Some(value)) = attributes.get_mut("srcset") {
+            let srcset = absolute_srcset(base, &value.as_utf8_str());
+            value.set(srcset)?;
+        }
+    }
+
+    Ok(dom.outer_html())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_base() {
+        let page = r#"<html><head><base href="/blog/"></head><body></body></html>"#;
+
+        assert_eq!(
+            document_base(page, "https://example.com/post/1")
+                .unwrap()
+                .as_str(),
+            "https://example.com/blog/"
+        );
+        assert_eq!(
+            document_base("<html></html>", "https://example.com/post/1")
+                .unwrap()
+                .as_str(),
+            "https://example.com/post/1"
+        );
+    }
+
+    #[test]
+    fn test_absolutize() {
+        let base = Url::parse("https://example.com/post/1").unwrap();
+        let html = r##"<p><a href="/about">About</a><a href="#note">Note</a><a href="https://other.com/">Other</a><img src="images/a.png" srcset="images/a.png 1x, /images/a-2x.png 2x"></p>"##;
+
+        assert_eq!(
+            absolutize(html, &base).unwrap(),
+            r##"<p><a href="https://example.com/about">About</a><a href="#note">Note</a><a href="https://other.com/">Other</a><img src="https://example.com/post/images/a.png" srcset="https://example.com/post/images/a.png 1x, https://example.com/images/a-2x.png 2x"></p>"##
+        );
+    }
+}
@@ -1,57 +1,627 @@
 #[cfg(feature = "proxy")]
 use {
     crate::{
-        parse::{add_http_prefix, get_rss_feed},
-        AppState, FurssOptions,
+        cache::{hash, now, FeedInfo},
+        feed::FeedFormat,
+        log_message,
+        metrics::{Metrics, FEED_REQUESTS},
+        parse::{add_http_prefix, get_rss_feed, RenderedFeed},
+        refresh::snapshot,
+        rules::SiteRules,
+        AppState, FurssOptions, LogLevel, APP_VERSION,
     },
     axum::{
-        extract::{Query, State},
-        http::{header::CONTENT_TYPE, HeaderMap, HeaderValue},
-        response::IntoResponse,
+        extract::{Path, Query, State},
+        http::{
+            header::{
+                ACCEPT, AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE,
+                IF_NONE_MATCH, LAST_MODIFIED,
+            },
+            HeaderMap, HeaderValue, Method, StatusCode, Uri,
+        },
+        response::{Html, IntoResponse},
+        routing::get,
+        Json, Router,
+    },
+    chrono::DateTime,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::hash_map::DefaultHasher,
+        env,
+        hash::{Hash, Hasher},
+    },
+    tower_http::{
+        cors::{Any, CorsLayer},
+        services::ServeDir,
     },
 };
 
 #[cfg(feature = "proxy")]
+const DEFAULT_MAX_AGE: u64 = 5 * 60;
+#[cfg(feature = "proxy")]
+const DEFAULT_STATIC_DIR: &str = "static";
+/// Paths browsers and crawlers ask for on their own, that are never feeds
+#[cfg(feature = "proxy")]
+const NOT_FEEDS: &[&str] = &[
+    "/favicon.ico",
+    "/robots.txt",
+    "/apple-touch-icon.png",
+    "/apple-touch-icon-precomposed.png",
+];
+/// Feeds whose version is remembered, the least recently served are forgotten first
+#[cfg(feature = "proxy")]
+const MAX_FEED_VERSIONS: usize = 1024;
+
+#[cfg(feature = "proxy")]
+/// Last `ETag` of a served feed, when it changed and when it was last served
+pub struct FeedVersion {
+    etag: String,
+    modified_at: u64,
+    served_at: u64,
+}
+
+#[cfg(feature = "proxy")]
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checks: Option<Checks>,
+}
+
+#[cfg(feature = "proxy")]
+#[derive(Serialize)]
+struct Checks {
+    /// The rules and subscriptions files are loaded
+    config: bool,
+    /// The cache directory is writable
+    cache: bool,
+}
+
+#[cfg(feature = "proxy")]
+impl Checks {
+    const fn is_ready(&self) -> bool {
+        self.config && self.cache
+    }
+}
+
+#[cfg(feature = "proxy")]
+/// Upstream of `/feed`, with its own query string
+#[derive(Deserialize)]
+pub struct FeedUrl {
+    url: String,
+}
+
+#[cfg(feature = "proxy")]
+/// The routes of furss, the fallback serves the feed whose url is the path
+pub fn app(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(|| async { INDEX }))
+        .route("/feed", get(feed_handler))
+        .route("/f/{name}", get(subscription_handler))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics))
+        .route("/admin/cache", get(list_cache).delete(purge_cache))
+        .nest_service("/static", ServeDir::new(static_dir()))
+        // Other methods get `405 Method Not Allowed`
+        .fallback(get(handler))
+        .layer(cors())
+        .with_state(state)
+}
+
+#[cfg(feature = "proxy")]
+fn version() -> &'static str {
+    APP_VERSION.get().map_or("", String::as_str)
+}
+
+#[cfg(feature = "proxy")]
+/// The process is alive
+async fn healthz() -> Json<Health> {
+    Json(Health {
+        status: "ok",
+        version: version(),
+        checks: None,
+    })
+}
+
+#[cfg(feature = "proxy")]
+/// The process can serve feeds, `503 Service Unavailable` otherwise
+async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Health>) {
+    let checks = Checks {
+        config: state.rules.is_loaded().await && state.subscriptions.is_loaded().await,
+        cache: state.cache.is_available().await,
+    };
+
+    let (status_code, status) = if checks.is_ready() {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+    };
+    (
+        status_code,
+        Json(Health {
+            status,
+            version: version(),
+            checks: Some(checks),
+        }),
+    )
+}
+
+#[cfg(feature = "proxy")]
+/// The metrics, for Prometheus
+async fn metrics() -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        Metrics::global().render(),
+    )
+}
+
+#[cfg(feature = "proxy")]
+/// Articles to remove from the cache, everything when `feed` isn't given
+#[derive(Deserialize)]
+pub struct Purge {
+    feed: Option<String>,
+    /// An article of `feed`, the whole feed when it isn't given
+    url: Option<String>,
+}
+
+#[cfg(feature = "proxy")]
+/// Compares the tokens in a time that doesn't depend on where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(feature = "proxy")]
+/// The admin API needs `Authorization: Bearer <ADMIN_TOKEN>`, and is disabled without
+/// `ADMIN_TOKEN`
+fn authorize(req_headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+    let token = env::var("ADMIN_TOKEN").unwrap_or_default();
+    if token.is_empty() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "The admin API is disabled".to_string(),
+        ));
+    }
+
+    let given = req_headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if given.is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes())) {
+        Ok(())
+    } else {
+        Err((StatusCode::UNAUTHORIZED, "Invalid admin token".to_string()))
+    }
+}
+
+#[cfg(feature = "proxy")]
+fn cache_error(error: &std::io::Error) -> (StatusCode, String) {
+    log_message!(LogLevel::Error, "ERROR accessing the cache: {error}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Error accessing the cache: {error}"),
+    )
+}
+
+#[cfg(feature = "proxy")]
+/// The cached feeds, with the url, age and size of their articles
+async fn list_cache(
+    req_headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<FeedInfo>>, (StatusCode, String)> {
+    authorize(&req_headers)?;
+
+    state
+        .cache
+        .list()
+        .await
+        .map(Json)
+        .map_err(|e| cache_error(&e))
+}
+
+#[cfg(feature = "proxy")]
+/// Removes an article, a feed or everything from the cache, so the articles are extracted again
+async fn purge_cache(
+    req_headers: HeaderMap,
+    Query(purge): Query<Purge>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    authorize(&req_headers)?;
+
+    let purged = match (&purge.feed, &purge.url) {
+        (None, None) => state.cache.flush().await.map(|()| true),
+        (Some(feed), None) => state.cache.purge_feed(feed).await,
+        (Some(feed), Some(url)) => state.cache.purge_article(feed, url).await,
+        (None, Some(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "The feed of the article is missing".to_string(),
+            ))
+        }
+    }
+    .map_err(|e| cache_error(&e))?;
+    if !purged {
+        return Err((StatusCode::NOT_FOUND, "Not in the cache".to_string()));
+    }
+
+    // The subscriptions are rendered with the purged articles until the next refresh
+    state.refreshed.lock().await.clear();
+    log_message!(
+        LogLevel::Info,
+        "Purged {} from the cache",
+        purge.url.or(purge.feed).as_deref().unwrap_or("everything")
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(feature = "proxy")]
+/// Directory of the files served at `/static`, from `STATIC_DIR`
+fn static_dir() -> String {
+    env::var("STATIC_DIR").unwrap_or_else(|_| DEFAULT_STATIC_DIR.to_string())
+}
+
+#[cfg(feature = "proxy")]
+/// Any origin can read the feeds, unless `CORS_ORIGINS` lists the allowed ones
+fn cors() -> CorsLayer {
+    let layer = CorsLayer::new()
+        .allow_methods([Method::GET, Method::HEAD])
+        .expose_headers([ETAG, LAST_MODIFIED]);
+
+    match env::var("CORS_ORIGINS") {
+        Ok(origins) => layer.allow_origin(
+            origins
+                .split(',')
+                .filter_map(|origin| origin.trim().parse::<HeaderValue>().ok())
+                .collect::<Vec<_>>(),
+        ),
+        Err(_) => layer.allow_origin(Any),
+    }
+}
+
+#[cfg(feature = "proxy")]
+/// The page previewing feeds with the options of furss
+const INDEX: Html<&str> = Html(include_str!("../static/index.html"));
+
+#[cfg(feature = "proxy")]
+/// Serves the feed whose url is the path, e.g. `/example.com/feed.xml`
+///
 /// # Errors
 ///
-/// Will return `Err` if there's an error when getting the rss feed
+/// Will return `Err` with the status code of the error when the feed can't be served
 pub async fn handler(
     req_headers: HeaderMap,
-    uri: axum::http::Uri,
+    uri: Uri,
     options: Query<FurssOptions>,
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    let options2: FurssOptions = options.0;
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    if NOT_FEEDS.contains(&uri.path()) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("{} isn't a feed", uri.path()),
+        ));
+    }
+
+    let rules = state.rules.get().await;
+    serve_feed(
+        &add_http_prefix(uri.path()),
+        options.0,
+        &rules,
+        &req_headers,
+        &state,
+    )
+    .await
+}
+
+#[cfg(feature = "proxy")]
+/// Serves the feed of the `url` parameter, e.g. `/feed?url=https%3A%2F%2Fexample.com%2Ffeed.xml`
+///
+/// # Errors
+///
+/// Will return `Err` with the status code of the error when the feed can't be served
+pub async fn feed_handler(
+    req_headers: HeaderMap,
+    Query(feed_url): Query<FeedUrl>,
+    options: Query<FurssOptions>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let rules = state.rules.get().await;
+    serve_feed(&feed_url.url, options.0, &rules, &req_headers, &state).await
+}
+
+#[cfg(feature = "proxy")]
+/// Serves a feed of the subscriptions file, e.g. `/f/lwn`
+///
+/// # Errors
+///
+/// Will return `Err` with the status code of the error when the feed isn't in the subscriptions
+/// file or can't be served
+pub async fn subscription_handler(
+    req_headers: HeaderMap,
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let subscriptions = state.subscriptions.get().await;
+    let subscription = subscriptions
+        .feeds
+        .get(&name)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Unknown feed {name}")))?;
+
+    // The refresher renders the feed in the format of the subscription
+    if subscription.options.format.is_some() || accepted_format(&req_headers).is_none() {
+        if let Some(response) = snapshot(&state, &name, subscription).await {
+            let key = version_key(&subscription.url, &subscription.options);
+            return respond(response, key, &req_headers, &state).await;
+        }
+    }
+
+    let rules = state.rules.get().await;
+
+    serve_feed(
+        &subscription.url,
+        subscription.options.clone(),
+        &rules,
+        &req_headers,
+        &state,
+    )
+    .await
+}
+
+#[cfg(feature = "proxy")]
+async fn serve_feed(
+    url: &str,
+    mut options: FurssOptions,
+    rules: &SiteRules,
+    req_headers: &HeaderMap,
+    state: &AppState,
+) -> Result<(StatusCode, HeaderMap, String), (StatusCode, String)> {
+    if options.format.is_none() {
+        options.format = accepted_format(req_headers);
+    }
+
+    let response = get_rss_feed(url, &options, &state.cache, rules)
+        .await
+        .map_err(|error| {
+            log_message!(LogLevel::Warn, "Error fetching {url}: {error}");
+            Metrics::global().increment(&FEED_REQUESTS, &[("outcome", error.kind())]);
+            (error.status_code(), error.to_string())
+        })?;
+
+    respond(response, version_key(url, &options), req_headers, state).await
+}
+
+#[cfg(feature = "proxy")]
+/// The feed with its caching headers, or `304 Not Modified` if the client has it already
+async fn respond(
+    response: RenderedFeed,
+    version_key: String,
+    req_headers: &HeaderMap,
+    state: &AppState,
+) -> Result<(StatusCode, HeaderMap, String), (StatusCode, String)> {
     let mut headers = HeaderMap::new();
+    let content_type = response.content_type.parse::<HeaderValue>().map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error setting Content-Type header".to_string(),
+        )
+    })?;
+    headers.insert(CONTENT_TYPE, content_type);
+
+    let etag = format!("\"{}\"", hash(&response.body));
+    let last_modified = last_modified(state, version_key, &etag).await;
+    if let Ok(value) = etag.parse() {
+        headers.insert(ETAG, value);
+    }
+    if let Some(value) = http_date(last_modified).and_then(|date| date.parse().ok()) {
+        headers.insert(LAST_MODIFIED, value);
+    }
+    if let Ok(value) = format!("public, max-age={}", max_age()).parse() {
+        headers.insert(CACHE_CONTROL, value);
+    }
 
-    let response = match req_headers.get(CONTENT_TYPE).map(HeaderValue::as_bytes) {
-        Some(b"application/xml") => {
-            (get_rss_feed(&add_http_prefix(uri.path()), &options2, &state).await).map_or_else(
-                |_| {
-                    Err((
-                        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                        "Error fetching RSS feed".to_string(),
-                    ))
-                },
-                |response| {
-                    "application/xml".parse::<HeaderValue>().map_or_else(
-                        |_| {
-                            Err((
-                                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                                "Error setting Content-Type header".to_string(),
-                            ))
-                        },
-                        |header_value| {
-                            headers.insert(CONTENT_TYPE, header_value);
-                            Ok((headers, response))
-                        },
-                    )
-                },
-            )
+    if is_not_modified(req_headers, &etag, last_modified) {
+        Metrics::global().increment(&FEED_REQUESTS, &[("outcome", "not_modified")]);
+        return Ok((StatusCode::NOT_MODIFIED, headers, String::new()));
+    }
+
+    Metrics::global().increment(&FEED_REQUESTS, &[("outcome", "ok")]);
+    Ok((StatusCode::OK, headers, response.body))
+}
+
+#[cfg(feature = "proxy")]
+/// The feed format asked with `Accept`, only when it names a single one of them, as readers
+/// accepting several formats are fine with the one of the upstream
+fn accepted_format(req_headers: &HeaderMap) -> Option<FeedFormat> {
+    let accept = req_headers.get(ACCEPT)?.to_str().ok()?;
+    let mut formats = accept.split(',').filter_map(FeedFormat::from_content_type);
+    let format = formats.next()?;
+
+    formats.all(|other| other == format).then_some(format)
+}
+
+#[cfg(feature = "proxy")]
+/// Seconds clients can keep the feed without asking again, from `CACHE_MAX_AGE`
+fn max_age() -> u64 {
+    env::var("CACHE_MAX_AGE")
+        .ok()
+        .and_then(|max_age| max_age.parse().ok())
+        .unwrap_or(DEFAULT_MAX_AGE)
+}
+
+#[cfg(feature = "proxy")]
+/// Identifies a served feed by its upstream url and the options it's rendered with
+fn version_key(url: &str, options: &FurssOptions) -> String {
+    let mut hasher = DefaultHasher::new();
+    options.hash(&mut hasher);
+
+    format!("{url} {:016x}", hasher.finish())
+}
+
+#[cfg(feature = "proxy")]
+/// When the feed of `key` last changed, in seconds since the epoch
+async fn last_modified(state: &AppState, key: String, etag: &str) -> u64 {
+    let now = now();
+    let mut versions = state.modified.lock().await;
+    if !versions.contains_key(&key) && versions.len() >= MAX_FEED_VERSIONS {
+        let least_recent = versions
+            .iter()
+            .min_by_key(|(_, version)| version.served_at)
+            .map(|(key, _)| key.clone());
+        if let Some(least_recent) = least_recent {
+            versions.remove(&least_recent);
         }
+    }
+
+    let version = versions.entry(key).or_insert_with(|| FeedVersion {
+        etag: etag.to_string(),
+        modified_at: now,
+        served_at: now,
+    });
+    if version.etag != etag {
+        version.etag = etag.to_string();
+        version.modified_at = now;
+    }
+    version.served_at = now;
+    let last_modified = version.modified_at;
+    drop(versions);
 
-        _ => Ok((headers, String::from("Hello, world!"))),
+    last_modified
+}
+
+#[cfg(feature = "proxy")]
+fn http_date(timestamp: u64) -> Option<String> {
+    DateTime::from_timestamp(i64::try_from(timestamp).ok()?, 0)
+        .map(|date| date.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+#[cfg(feature = "proxy")]
+/// `If-None-Match` takes precedence over `If-Modified-Since`
+fn is_not_modified(req_headers: &HeaderMap, etag: &str, last_modified: u64) -> bool {
+    let header = |name| {
+        req_headers
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
     };
 
-    response
+    if let Some(if_none_match) = header(IF_NONE_MATCH) {
+        return if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+
+    header(IF_MODIFIED_SINCE)
+        .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+        .and_then(|date| u64::try_from(date.timestamp()).ok())
+        .is_some_and(|since| last_modified <= since)
+}
+
+#[cfg(all(test, feature = "proxy"))]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::config::WatchedFile;
+
+    #[test]
+    fn test_is_not_modified() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_not_modified(&headers, "\"abc\"", 1_716_732_000));
+
+        headers.insert(
+            IF_NONE_MATCH,
+            HeaderValue::from_static("\"def\", W/\"abc\""),
+        );
+        assert!(is_not_modified(&headers, "\"abc\"", 1_716_732_000));
+        assert!(!is_not_modified(&headers, "\"ghi\"", 1_716_732_000));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Sun, 26 May 2024 14:00:00 GMT"),
+        );
+        assert!(is_not_modified(&headers, "\"abc\"", 1_716_732_000));
+        assert!(!is_not_modified(&headers, "\"abc\"", 1_716_732_001));
+    }
+
+    #[test]
+    fn test_accepted_format() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(accepted_format(&headers), None);
+
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/feed+json, */*;q=0.8"),
+        );
+        assert_eq!(accepted_format(&headers), Some(FeedFormat::Json));
+
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/rss+xml, application/atom+xml;q=0.9"),
+        );
+        assert_eq!(accepted_format(&headers), None);
+    }
+
+    #[test]
+    fn test_checks_is_ready() {
+        let mut checks = Checks {
+            config: true,
+            cache: true,
+        };
+        assert!(checks.is_ready());
+
+        checks.cache = false;
+        assert!(!checks.is_ready());
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+
+    #[test]
+    fn test_http_date() {
+        assert_eq!(
+            http_date(1_716_732_000).as_deref(),
+            Some("Sun, 26 May 2024 14:00:00 GMT")
+        );
+    }
+
+    #[test]
+    fn test_version_key() {
+        let full: FurssOptions = toml::from_str("full = true").unwrap();
+        let url = "https://example.com/feed.xml";
+
+        assert_eq!(
+            version_key(url, &FurssOptions::default()),
+            version_key(url, &FurssOptions::default())
+        );
+        assert_ne!(
+            version_key(url, &FurssOptions::default()),
+            version_key(url, &full)
+        );
+        assert_ne!(
+            version_key(url, &full),
+            version_key("https://example.org/feed.xml", &full)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_last_modified_is_bounded() {
+        let state = AppState {
+            cache: Arc::default(),
+            rules: Arc::new(WatchedFile::new(None)),
+            subscriptions: Arc::new(WatchedFile::new(None)),
+            refreshed: Arc::default(),
+            modified: Arc::default(),
+        };
+
+        for feed in 0..=MAX_FEED_VERSIONS {
+            last_modified(&state, feed.to_string(), "\"abc\"").await;
+        }
+        assert_eq!(state.modified.lock().await.len(), MAX_FEED_VERSIONS);
+    }
 }
@@ -0,0 +1,144 @@
+use std::borrow::Cow;
+
+use reqwest::Url;
+use serde::Deserialize;
+
+/// Extraction rules of a site, matched on the host of the article url.
+///
+/// `domain` is either a host (`example.com`), a wildcard (`*.example.com`)
+/// that also matches the domain itself, or `*` that matches every host.
+#[derive(Clone, Deserialize)]
+pub struct SiteRule {
+    pub domain: String,
+    /// Selector of the element holding the article
+    pub selector: Option<String>,
+    /// Selectors of the elements removed from the article
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct SiteRules {
+    #[serde(default, rename = "site")]
+    pub sites: Vec<SiteRule>,
+}
+
+impl SiteRule {
+    fn matches(&self, host: &str) -> bool {
+        let domain = self.domain.to_lowercase();
+        if domain == "*" {
+            return true;
+        }
+        domain.strip_prefix("*.").map_or(host == domain, |parent| {
+            host == parent || host.ends_with(&format!(".{parent}"))
+        })
+    }
+}
+
+impl SiteRules {
+    /// Returns the first rule matching the host of `url`
+    #[must_use]
+    pub fn find(&self, url: &str) -> Option<&SiteRule> {
+        let host = Url::parse(url).ok()?.host_str()?.to_lowercase();
+
+        self.sites.iter().find(|rule| rule.matches(&host))
+    }
+
+    /// These rules, with `selector` and the comma separated selectors of `remove` merged into
+    /// each of them, and a rule matching every other host holding only the given selectors.
+    /// `selector` replaces the selector of a rule, `remove` is added to its removed selectors.
+    #[must_use]
+    pub fn with_selectors(&self, selector: Option<&str>, remove: Option<&str>) -> Cow<'_, Self> {
+        let remove: Vec<String> = remove
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|selector| !selector.is_empty())
+            .map(ToString::to_string)
+            .collect();
+        if selector.is_none() && remove.is_empty() {
+            return Cow::Borrowed(self);
+        }
+
+        let merge = |rule: &SiteRule| SiteRule {
+            domain: rule.domain.clone(),
+            selector: selector
+                .map(ToString::to_string)
+                .or_else(|| rule.selector.clone()),
+            remove: rule.remove.iter().chain(&remove).cloned().collect(),
+        };
+        let fallback = SiteRule {
+            domain: "*".to_string(),
+            selector: None,
+            remove: Vec::new(),
+        };
+        Cow::Owned(Self {
+            sites: self
+                .sites
+                .iter()
+                .chain(std::iter::once(&fallback))
+                .map(merge)
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r##"
+[[site]]
+domain = "*.example.com"
+selector = "div.story"
+remove = [".share", ".related"]
+
+[[site]]
+domain = "example.org"
+selector = "#content"
+"##;
+
+    #[test]
+    fn test_find_rule() {
+        let rules: SiteRules = toml::from_str(RULES).unwrap();
+
+        let rule = rules.find("https://www.example.com/article").unwrap();
+        assert_eq!(rule.selector.as_deref(), Some("div.story"));
+        assert_eq!(rule.remove, vec![".share", ".related"]);
+        assert!(rules.find("https://example.com/article").is_some());
+        assert!(rules.find("https://example.org/article").is_some());
+        assert!(rules.find("https://www.example.org/article").is_none());
+        assert!(rules.find("https://notexample.com/article").is_none());
+
+        let rules: SiteRules = toml::from_str("[[site]]\ndomain = \"*\"").unwrap();
+        assert!(rules.find("https://notexample.com/article").is_some());
+    }
+
+    #[test]
+    fn test_with_selectors() {
+        let rules: SiteRules = toml::from_str(RULES).unwrap();
+        assert!(matches!(
+            rules.with_selectors(None, Some(" ")),
+            Cow::Borrowed(_)
+        ));
+
+        let rules = rules.with_selectors(Some("main"), Some(".ad, aside"));
+        let rule = rules.find("https://www.example.com/article").unwrap();
+        assert_eq!(rule.selector.as_deref(), Some("main"));
+        assert_eq!(rule.remove, vec![".share", ".related", ".ad", "aside"]);
+        assert!(rules.find("https://notexample.com/article").is_some());
+    }
+
+    #[test]
+    fn test_with_remove_only() {
+        let rules: SiteRules = toml::from_str(RULES).unwrap();
+        let rules = rules.with_selectors(None, Some("aside"));
+
+        let rule = rules.find("https://www.example.com/article").unwrap();
+        assert_eq!(rule.selector.as_deref(), Some("div.story"));
+        assert_eq!(rule.remove, vec![".share", ".related", "aside"]);
+        let rule = rules.find("https://notexample.com/article").unwrap();
+        assert_eq!(rule.selector, None);
+        assert_eq!(rule.remove, vec!["aside"]);
+    }
+}
@@ -0,0 +1,265 @@
+use std::{collections::HashSet, env, error::Error, sync::OnceLock};
+
+use tl::{Node, NodeHandle, Parser};
+
+use crate::html::{is_void, push_end_tag, push_start_tag};
+
+/// Comma separated, like `SANITIZER_TAGS`
+const DEFAULT_TAGS: &str = "a,abbr,article,aside,audio,b,blockquote,br,caption,cite,code,col,\
+    colgroup,dd,del,details,div,dl,dt,em,figcaption,figure,h1,h2,h3,h4,h5,h6,hr,i,img,ins,kbd,li,\
+    main,mark,ol,p,picture,pre,q,s,section,small,source,span,strong,sub,summary,sup,table,tbody,td,\
+    tfoot,th,thead,time,tr,u,ul,video";
+/// Comma separated, like `SANITIZER_ATTRIBUTES`
+const DEFAULT_ATTRIBUTES: &str = "alt,cite,colspan,controls,datetime,dir,height,href,lang,media,\
+    poster,rowspan,sizes,src,srcset,title,type,width";
+/// Elements removed with everything inside them, whatever the allow-list
+const DROPPED_ELEMENTS: &[&str] = &[
+    "script", "style", "iframe", "frame", "frameset", "object", "embed", "applet", "noscript",
+    "template", "form", "svg", "math", "head", "title", "meta", "link", "base",
+];
+const URL_ATTRIBUTES: &[&str] = &["href", "src", "srcset", "cite", "poster"];
+
+static SANITIZER: OnceLock<Sanitizer> = OnceLock::new();
+
+/// Allow-list html sanitizer.
+///
+/// Elements that are not allowed are replaced by their content, attributes that are not
+/// allowed are removed. Event handlers, `javascript:` urls and tracking pixels are always removed.
+pub struct Sanitizer {
+    tags: HashSet<String>,
+    attributes: HashSet<String>,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        Self::new(split_list(DEFAULT_TAGS), split_list(DEFAULT_ATTRIBUTES))
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = String> + '_ {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_lowercase)
+}
+
+impl Sanitizer {
+    pub fn new(
+        tags: impl IntoIterator<Item = String>,
+        attributes: impl IntoIterator<Item = String>,
+    ) -> Self {
+        Self {
+            tags: tags.into_iter().collect(),
+            attributes: attributes.into_iter().collect(),
+        }
+    }
+
+    /// Reads the comma separated allow-lists `SANITIZER_TAGS` and `SANITIZER_ATTRIBUTES`,
+    /// falling back to the defaults
+    #[must_use]
+    pub fn from_env() -> Self {
+        let default = Self::default();
+
+        Self {
+            tags: env::var("SANITIZER_TAGS")
+                .map_or(default.tags, |tags| split_list(&tags).collect()),
+            attributes: env::var("SANITIZER_ATTRIBUTES").map_or(default.attributes, |attributes| {
+                split_list(&attributes).collect()
+            }),
+        }
+    }
+
+    /// The sanitizer configured from the environment
+    pub fn global() -> &'static Self {
+        SANITIZER.get_or_init(Self::from_env)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the html can't be parsed
+    pub fn sanitize(&self, html: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let dom = tl::parse(html, tl::ParserOptions::default())?;
+        let mut sanitized = String::with_capacity(html.len());
+
+        for handle in dom.children() {
+            self.write_node(*handle, dom.parser(), &mut sanitized);
+        }
+
+        Ok(sanitized)
+    }
+
+    fn write_node(&self, handle: NodeHandle, parser: &Parser, html: &mut String) {
+        match handle.get(parser) {
+            Some(Node::Tag(tag)) => {
+                let name = tag.name().as_utf8_str().to_lowercase();
+                if DROPPED_ELEMENTS.contains(&name.as_str()) || is_tracking_pixel(tag) {
+                    return;
+                }
+
+                let allowed = self.tags.contains(&name);
+                if allowed {
+                    push_start_tag(
+                        html,
+                        &name,
+                        tag.attributes().iter().filter(|(key, value)| {
+                            self.is_allowed_attribute(key, value.as_deref())
+                        }),
+                    );
+                }
+                if is_void(&name) {
+                    return;
+                }
+                for child in tag.children().top().iter() {
+                    self.write_node(*child, parser, html);
+                }
+                if allowed {
+                    push_end_tag(html, &name);
+                }
+            }
+            Some(Node::Raw(text)) => html.push_str(&text.as_utf8_str()),
+            Some(Node::Comment(_)) | None => (),
+        }
+    }
+
+    fn is_allowed_attribute(&self, key: &str, value: Option<&str>) -> bool {
+        let key = key.to_lowercase();
+        if key.starts_with("on") || !self.attributes.contains(&key) {
+            return false;
+        }
+
+        !URL_ATTRIBUTES.contains(&key.as_str()) || value.is_none_or(is_safe_url)
+    }
+}
+
+/// Whether the scheme of `url` is safe once its entities are decoded, as browsers do before
+/// following it, and its whitespace and control characters, that browsers ignore, are removed
+fn is_safe_url(url: &str) -> bool {
+    let url: String = decode_entities(url)
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
+
+    !(url.starts_with("javascript:")
+        || url.starts_with("vbscript:")
+        || (url.starts_with("data:") && !url.starts_with("data:image/")))
+}
+
+/// `value` with its numeric character references, with or without the trailing `;`, and the
+/// named ones that can hide a scheme decoded
+fn decode_entities(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        if let Some((character, len)) = decode_entity(rest) {
+            decoded.push(character);
+            rest = &rest[len..];
+        } else {
+            decoded.push('&');
+        }
+    }
+    decoded.push_str(rest);
+
+    decoded
+}
+
+/// The character of the entity at the start of `entity`, after its `&`, and its length
+fn decode_entity(entity: &str) -> Option<(char, usize)> {
+    if let Some(number) = entity.strip_prefix('#') {
+        let (radix, digits) = number
+            .strip_prefix(['x', 'X'])
+            .map_or((10, number), |hex| (16, hex));
+        let end = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        let character = char::from_u32(u32::from_str_radix(&digits[..end], radix).ok()?)?;
+        let semicolon = usize::from(digits[end..].starts_with(';'));
+        return Some((character, entity.len() - digits.len() + end + semicolon));
+    }
+
+    let end = entity.find(';')?;
+    let character = match entity[..end].to_lowercase().as_str() {
+        "amp" => '&',
+        "colon" => ':',
+        "tab" => '\t',
+        "newline" => '\n',
+        _ => return None,
+    };
+    Some((character, end + 1))
+}
+
+/// Images of 1x1 pixel or less
+fn is_tracking_pixel(tag: &tl::HTMLTag) -> bool {
+    let size = |name: &str| {
+        tag.attributes().get(name).flatten().and_then(|value| {
+            value
+                .as_utf8_str()
+                .trim_end_matches("px")
+                .parse::<u32>()
+                .ok()
+        })
+    };
+
+    tag.name().as_utf8_str().eq_ignore_ascii_case("img")
+        && size("width").is_some_and(|width| width <= 1)
+        && size("height").is_some_and(|height| height <= 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_removes_scripts_and_handlers() {
+        let html = r#"<article><h1 onclick="alert(1)">Title</h1><div><script>alert(1)</script><p style="color: red">Text</p></div><style>p { color: red }</style></article>"#;
+
+        assert_eq!(
+            Sanitizer::default().sanitize(html).unwrap(),
+            "<article><h1>Title</h1><div><p>Text</p></div></article>"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_keeps_content_of_unknown_tags() {
+        let html = r#"<article><custom-element><p>Text</p></custom-element><iframe src="https://example.com"></iframe></article>"#;
+
+        assert_eq!(
+            Sanitizer::default().sanitize(html).unwrap(),
+            "<article><p>Text</p></article>"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_removes_unsafe_urls_and_tracking_pixels() {
+        let html = r#"<p><a href="javascript:alert(1)">Link</a><a href="https://example.com">Link</a><img src="https://example.com/a.png" alt="A"><img src="https://tracker.com/pixel.gif" width="1" height="1"></p>"#;
+
+        assert_eq!(
+            Sanitizer::default().sanitize(html).unwrap(),
+            r#"<p><a>Link</a><a href="https://example.com">Link</a><img src="https://example.com/a.png" alt="A"></p>"#
+        );
+    }
+
+    #[test]
+    fn test_is_safe_url_decodes_entities() {
+        assert!(!is_safe_url("&#106;avascript:alert(1)"));
+        assert!(!is_safe_url("&#x6A;avascript:alert(1)"));
+        assert!(!is_safe_url("&#106avascript:alert(1)"));
+        assert!(!is_safe_url("java&#x09;script:alert(1)"));
+        assert!(!is_safe_url("java\tscript:alert(1)"));
+        assert!(!is_safe_url("javascript&colon;alert(1)"));
+        assert!(!is_safe_url(" \u{1}javascript:alert(1)"));
+        assert!(is_safe_url("https://example.com/?a=1&amp;b=2"));
+        assert!(is_safe_url("https://example.com/?a=1&b=2"));
+    }
+
+    #[test]
+    fn test_sanitize_custom_allow_list() {
+        let sanitizer = Sanitizer::new(["p".to_string()], []);
+        let html = r#"<div><p class="a">Text <b>bold</b></p></div>"#;
+
+        assert_eq!(sanitizer.sanitize(html).unwrap(), "<p>Text bold</p>");
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::FurssOptions;
+
+/// A feed served at `/f/<name>`, with its options kept server-side
+#[derive(Clone, Deserialize, Hash)]
+pub struct Subscription {
+    pub url: String,
+    #[serde(flatten)]
+    pub options: FurssOptions,
+}
+
+#[derive(Default, Deserialize)]
+pub struct Subscriptions {
+    #[serde(default)]
+    pub feeds: HashMap<String, Subscription>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUBSCRIPTIONS: &str = r#"
+[feeds.lwn]
+url = "https://lwn.net/headlines/rss"
+full = true
+number_items = 20
+selector = "div.ArticleText"
+remove = [".Byline", ".FeatureByline"]
+
+[feeds.example]
+url = "https://example.com/feed.xml?page=1"
+remove = "aside"
+"#;
+
+    #[test]
+    fn test_subscriptions() {
+        let subscriptions: Subscriptions = toml::from_str(SUBSCRIPTIONS).unwrap();
+
+        let lwn = &subscriptions.feeds["lwn"];
+        assert_eq!(lwn.url, "https://lwn.net/headlines/rss");
+        assert_eq!(lwn.options.full, Some(true));
+        assert_eq!(lwn.options.number_items, Some(20));
+        assert_eq!(lwn.options.selector.as_deref(), Some("div.ArticleText"));
+        assert_eq!(
+            lwn.options.remove.as_deref(),
+            Some(".Byline, .FeatureByline")
+        );
+
+        let example = &subscriptions.feeds["example"];
+        assert_eq!(example.options.full, None);
+        assert_eq!(example.options.selector, None);
+        assert_eq!(example.options.remove.as_deref(), Some("aside"));
+    }
+}